@@ -1,9 +1,11 @@
 use std::ops::Range;
+use num::FromPrimitive;
 use num_derive::{FromPrimitive, ToPrimitive};
+use serde::{Serialize, Deserialize};
 
-pub const NUM_NEURONS: usize = 32;
+pub const NUM_NEURONS: usize = 40;
 
-#[derive(Debug, FromPrimitive, ToPrimitive)]
+#[derive(Debug, Clone, Copy, FromPrimitive, ToPrimitive)]
 pub enum Input {
     Constant,
     Oscillator,
@@ -12,6 +14,14 @@ pub enum Input {
     SafeX,
     SafeY,
     SafeRadius,
+    ZoneDX,
+    ZoneDY,
+    ZoneEdge,
+    WallDistance,
+    Sin0,
+    Cos0,
+    Sin1,
+    Cos1,
     Number
 }
 
@@ -29,12 +39,87 @@ pub const INPUT_INDICES: Range<usize> = 0..(Input::Number as usize);
 pub const OUTPUT_INDICES: Range<usize> = (Input::Number as usize)..(Output::Number as usize);
 pub const FIXED_INDICES: Range<usize> = 0..(Output::Number as usize);
 
+/// Which neuron is which, recorded in log headers so that genomes can be
+/// carried over to builds with other inputs or more neurons.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BrainLayout {
+    pub neurons: usize,
+    /// Names and indices of the input neurons.
+    pub inputs: Vec<(String, usize)>,
+    pub outputs: Vec<(String, usize)>,
+}
+
+impl BrainLayout {
+    /// The layout of this build.
+    pub fn current() -> BrainLayout {
+        BrainLayout {
+            neurons: NUM_NEURONS,
+            inputs: INPUT_INDICES
+                .map(|i| (format!("{:?}", Input::from_usize(i).unwrap()), i))
+                .collect(),
+            outputs: OUTPUT_INDICES
+                .map(|i| (format!("{:?}", Output::from_usize(i).unwrap()), i))
+                .collect(),
+        }
+    }
+
+    /// Neurons that are neither inputs nor outputs, in order.
+    pub fn hidden(&self) -> Vec<usize> {
+        (0..self.neurons)
+            .filter(|i| !self.inputs.iter().chain(&self.outputs).any(|(_, index)| index == i))
+            .collect()
+    }
+
+    /// Where each neuron of this layout goes in `to`: inputs and outputs by
+    /// name, and hidden neurons in order. `None` for neurons that `to` has no
+    /// place for.
+    pub fn map_onto(&self, to: &BrainLayout) -> Vec<Option<usize>> {
+        let mut map = vec![None; self.neurons];
+        for (fixed, to_fixed) in [(&self.inputs, &to.inputs), (&self.outputs, &to.outputs)] {
+            for (name, index) in fixed {
+                map[*index] = to_fixed.iter().find(|(to_name, _)| to_name == name).map(|(_, to_index)| *to_index);
+            }
+        }
+        for (from, to) in self.hidden().into_iter().zip(to.hidden()) {
+            map[from] = Some(to);
+        }
+        map
+    }
+}
+
+/// Which kind of controller agents are built with.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default, clap::ArgEnum)]
+pub enum BrainType {
+    /// Synchronous discrete-time network (`Brain`).
+    #[default]
+    Discrete,
+    /// Continuous-time recurrent network (`Ctrnn`).
+    Ctrnn,
+}
+
+/// Common interface of everything that can drive an agent.
+pub trait Controller {
+    fn input(&mut self, input: Input, value: f32);
+    fn output(&self, output: Output) -> f32;
+
+    /// Advance the controller by `time_step` seconds.
+    fn simulate(&mut self, time_step: f32);
+
+    /// Everything that changes as the controller runs, for checkpoints.
+    fn state(&self) -> Vec<f32>;
+    fn set_state(&mut self, state: &[f32]);
+
+    /// Current activation of every neuron.
+    fn activations(&self) -> Vec<f32>;
+}
+
 pub struct Brain {
     pub weights: [[f32; NUM_NEURONS]; NUM_NEURONS],
+    pub biases: [f32; NUM_NEURONS],
     activation: [f32; NUM_NEURONS],
 }
 
-fn activation_function(index: usize, value: f32) -> f32 {
+pub fn activation_function(index: usize, value: f32) -> f32 {
     if index == Output::SpeedX as usize || index == Output::SpeedY as usize {
         2.0 / (1.0 + (-4.0 * value).exp()) - 1.0
     }
@@ -48,28 +133,44 @@ impl Brain {
     pub fn new() -> Brain {
         Brain {
             weights: [[0.0; NUM_NEURONS]; NUM_NEURONS],
+            biases: [0.0; NUM_NEURONS],
             activation: [0.0; NUM_NEURONS],
         }
     }
+}
 
-    pub fn input(&mut self, input: Input, value: f32) {
+impl Controller for Brain {
+    fn input(&mut self, input: Input, value: f32) {
         self.activation[input as usize] = value;
     }
 
-    pub fn output(&self, output: Output) -> f32 {
+    fn output(&self, output: Output) -> f32 {
         self.activation[output as usize]
     }
 
-    pub fn simulate(&mut self) {
+    /// Synchronous discrete update; one step per call regardless of
+    /// `time_step`.
+    fn simulate(&mut self, _time_step: f32) {
         let mut new_activation = [0.0; NUM_NEURONS];
         for j in 0..NUM_NEURONS {
             for i in 0..NUM_NEURONS {
-                if i == j { continue; }
                 new_activation[j] += self.activation[i] * self.weights[i][j];
             }
-            new_activation[j] = activation_function(j, new_activation[j] + self.weights[j][j]);
+            new_activation[j] = activation_function(j, new_activation[j] + self.biases[j]);
         }
 
         self.activation = new_activation;
     }
+
+    fn state(&self) -> Vec<f32> {
+        self.activation.to_vec()
+    }
+
+    fn set_state(&mut self, state: &[f32]) {
+        self.activation.copy_from_slice(state);
+    }
+
+    fn activations(&self) -> Vec<f32> {
+        self.activation.to_vec()
+    }
 }
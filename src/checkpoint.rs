@@ -0,0 +1,121 @@
+//! Checkpoints of the full state of a run, so that it can be resumed exactly
+//! where it stopped, for example after a preemptible machine is reclaimed.
+//!
+//! Checkpoints are written to `<run id>.checkpoint` next to the log (in
+//! `output` if the log isn't written to a directory), by writing a temporary
+//! file and renaming it over the previous checkpoint.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use rand_chacha::ChaCha8Rng;
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+
+use super::brain::BrainType;
+use super::history::{AgentEntry, Header, LogPosition, Outcome};
+use super::log_format::LogFormat;
+use super::physics::Motion;
+use super::sensors::DelayLine;
+use super::trajectory::Recording;
+use super::{genetics, Agent, Progress, Settings};
+
+#[derive(Serialize, Deserialize)]
+pub struct AgentState {
+    pub id: Uuid,
+    pub parent: Option<Uuid>,
+    pub genome: Vec<f32>,
+    pub position: (f32, f32),
+    pub motion: Motion,
+    pub brain: Vec<f32>,
+    pub sensor_delay: DelayLine,
+    #[serde(default)]
+    pub outcome: Outcome,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Header of the log being written.
+    pub header: Header,
+    pub format: LogFormat,
+    pub log: LogPosition,
+    /// Header and agents the run is reseeded from if it goes extinct.
+    pub start_header: Header,
+    pub start_agents: Vec<AgentEntry>,
+    pub settings: Settings,
+    pub generation: usize,
+    pub progress: Progress,
+    /// Seconds spent simulating the current generation so far.
+    pub wall_time: f32,
+    pub agents: Vec<AgentState>,
+    /// Trajectories of the current generation recorded so far.
+    #[serde(default)]
+    pub recording: Option<Recording>,
+    pub rng: ChaCha8Rng,
+}
+
+pub fn checkpoint_path(directory: &str, run: Uuid) -> PathBuf {
+    Path::new(directory).join(format!("{}.checkpoint", run))
+}
+
+impl Checkpoint {
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let temporary = path.with_extension("checkpoint.tmp");
+        let mut write = BufWriter::new(File::create(&temporary)?);
+        serde_json::to_writer(&mut write, self)?;
+        write.flush()?;
+        write.get_ref().sync_all()?;
+        std::fs::rename(temporary, path)
+    }
+
+    /// Reads a checkpoint, rejecting agents whose genome or brain state
+    /// doesn't fit this build and the checkpoint's brain type.
+    pub fn load(path: &str) -> io::Result<Checkpoint> {
+        let checkpoint: Checkpoint = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        let state_len = genetics::create_controller(&[0.0; genetics::NUM_CODONS], checkpoint.settings.brain).state().len();
+        for agent in &checkpoint.agents {
+            if agent.genome.len() != genetics::NUM_CODONS {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                    "agent {} has {} codons, expected {}", agent.id, agent.genome.len(), genetics::NUM_CODONS)));
+            }
+            if agent.brain.len() != state_len {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                    "agent {} has a brain state of length {}, expected {}", agent.id, agent.brain.len(), state_len)));
+            }
+        }
+        Ok(checkpoint)
+    }
+}
+
+impl Agent {
+    pub fn to_checkpoint(&self) -> AgentState {
+        AgentState {
+            id: self.uuid,
+            parent: self.parent,
+            genome: self.genome.to_vec(),
+            position: self.position,
+            motion: self.motion.clone(),
+            brain: self.brain.state(),
+            sensor_delay: self.sensor_delay.clone(),
+            outcome: self.outcome.clone(),
+        }
+    }
+
+    /// Expects a state validated by `Checkpoint::load`.
+    pub fn from_checkpoint(state: AgentState, brain_type: BrainType) -> Agent {
+        let genome: genetics::Genome = state.genome.try_into().unwrap();
+        let mut brain = genetics::create_controller(&genome, brain_type);
+        brain.set_state(&state.brain);
+        Agent {
+            uuid: state.id,
+            parent: state.parent,
+            position: state.position,
+            genome,
+            brain,
+            sensor_delay: state.sensor_delay,
+            motion: state.motion,
+            outcome: state.outcome,
+        }
+    }
+}
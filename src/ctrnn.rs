@@ -0,0 +1,75 @@
+//! Continuous-time recurrent neural network.
+//!
+//! Every non-input neuron integrates
+//!
+//!     tau_j * dy_j/dt = -y_j + sum_i(w_ij * a_i) + b_j
+//!
+//! where `a_i` is the activation of neuron `i`. The state is advanced
+//! with the exact solution for a constant right-hand side, which is stable
+//! for any time step and converges as the time step shrinks.
+
+use super::brain::{NUM_NEURONS, INPUT_INDICES, Input, Output, Controller, activation_function};
+
+pub struct Ctrnn {
+    pub weights: [[f32; NUM_NEURONS]; NUM_NEURONS],
+    pub biases: [f32; NUM_NEURONS],
+    pub time_constants: [f32; NUM_NEURONS],
+    state: [f32; NUM_NEURONS],
+    activation: [f32; NUM_NEURONS],
+}
+
+impl Ctrnn {
+    pub fn new() -> Ctrnn {
+        Ctrnn {
+            weights: [[0.0; NUM_NEURONS]; NUM_NEURONS],
+            biases: [0.0; NUM_NEURONS],
+            time_constants: [1.0; NUM_NEURONS],
+            state: [0.0; NUM_NEURONS],
+            activation: [0.0; NUM_NEURONS],
+        }
+    }
+}
+
+impl Controller for Ctrnn {
+    fn input(&mut self, input: Input, value: f32) {
+        self.activation[input as usize] = value;
+    }
+
+    fn output(&self, output: Output) -> f32 {
+        self.activation[output as usize]
+    }
+
+    fn simulate(&mut self, time_step: f32) {
+        let mut target = [0.0; NUM_NEURONS];
+        for (j, (target, bias)) in target.iter_mut().zip(&self.biases).enumerate() {
+            *target = self.activation.iter().zip(&self.weights)
+                .map(|(activation, weights)| activation * weights[j])
+                .sum::<f32>() + bias;
+        }
+
+        let neurons = self.state.iter_mut().zip(&mut self.activation).zip(target.iter().zip(&self.time_constants));
+        for (j, ((state, activation), (target, time_constant))) in neurons.enumerate() {
+            if INPUT_INDICES.contains(&j) {
+                // Inputs are clamped to whatever was fed in.
+                continue;
+            }
+            let decay = (-time_step / time_constant).exp();
+            *state = target + (*state - target) * decay;
+            *activation = activation_function(j, *state);
+        }
+    }
+
+    fn state(&self) -> Vec<f32> {
+        self.state.iter().chain(&self.activation).copied().collect()
+    }
+
+    fn set_state(&mut self, state: &[f32]) {
+        let (state, activation) = state.split_at(NUM_NEURONS);
+        self.state.copy_from_slice(state);
+        self.activation.copy_from_slice(activation);
+    }
+
+    fn activations(&self) -> Vec<f32> {
+        self.activation.to_vec()
+    }
+}
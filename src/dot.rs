@@ -1,14 +1,40 @@
+use std::collections::HashSet;
 use std::io::Write;
 
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+
+use super::lineage::Lineage;
+use super::runs::Runs;
 use super::brain::{NUM_NEURONS, FIXED_INDICES, INPUT_INDICES, OUTPUT_INDICES, Input, Output};
 
-impl super::Brain {
-    pub fn draw_graph(&self, file: &mut impl Write) {
-        writeln!(file, "digraph {{").unwrap();
-        writeln!(file, "splines=false;").unwrap();
-        writeln!(file, "rankdir=\"LR\";").unwrap();
+/// The connections of a brain that matter, and the neurons they connect.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Graph {
+    /// All inputs and outputs, and hidden neurons with a connection.
+    pub neurons: Vec<Neuron>,
+    pub connections: Vec<Connection>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Neuron {
+    pub index: usize,
+    /// `IN:<input>`, `OUT:<output>`, or the index of a hidden neuron.
+    pub label: String,
+    pub bias: f32,
+}
 
-        let mut relevant_neurons = std::collections::HashSet::<usize>::new();
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Connection {
+    pub from: usize,
+    pub to: usize,
+    pub weight: f32,
+}
+
+impl super::Brain {
+    pub fn graph(&self) -> Graph {
+        let mut connections = vec![];
+        let mut relevant_neurons = std::collections::BTreeSet::<usize>::new();
         for i in 0..NUM_NEURONS {
             for j in 0..NUM_NEURONS {
                 const EPSILON: f32 = 0.0001;
@@ -20,30 +46,108 @@ impl super::Brain {
                 // May form an island, but are at least not individually isolated.
                 if !FIXED_INDICES.contains(&i) { relevant_neurons.insert(i); }
                 if !FIXED_INDICES.contains(&j) { relevant_neurons.insert(j); }
-                writeln!(file, "_{} -> _{} [penwidth={}];", i, j, self.weights[i][j].abs() * 10.0).unwrap();
+                connections.push(Connection { from: i, to: j, weight: self.weights[i][j] });
             }
         }
 
-        writeln!(file, "subgraph cluster_inputs {{").unwrap();
-        writeln!(file, "peripheries=0;").unwrap();
+        let mut neurons = vec![];
         for i in INPUT_INDICES {
             let input: Input = num::FromPrimitive::from_usize(i).unwrap();
-            writeln!(file, "_{} [label=\"IN:{:?}\", bgcolor=\"blue\"]", i, input).unwrap();
+            neurons.push(Neuron { index: i, label: format!("IN:{:?}", input), bias: self.biases[i] });
+        }
+        for i in relevant_neurons {
+            neurons.push(Neuron { index: i, label: format!("{}", i), bias: self.biases[i] });
+        }
+        for i in OUTPUT_INDICES {
+            let output: Output = num::FromPrimitive::from_usize(i).unwrap();
+            neurons.push(Neuron { index: i, label: format!("OUT:{:?}", output), bias: self.biases[i] });
+        }
+
+        Graph { neurons, connections }
+    }
+
+    pub fn draw_graph(&self, file: &mut impl Write) {
+        let graph = self.graph();
+
+        writeln!(file, "digraph {{").unwrap();
+        writeln!(file, "splines=false;").unwrap();
+        writeln!(file, "rankdir=\"LR\";").unwrap();
+
+        for connection in &graph.connections {
+            writeln!(file, "_{} -> _{} [penwidth={}];", connection.from, connection.to, connection.weight.abs() * 10.0).unwrap();
+        }
+
+        writeln!(file, "subgraph cluster_inputs {{").unwrap();
+        writeln!(file, "peripheries=0;").unwrap();
+        for neuron in graph.neurons.iter().filter(|neuron| INPUT_INDICES.contains(&neuron.index)) {
+            writeln!(file, "_{} [label=\"{}\", bgcolor=\"blue\"]", neuron.index, neuron.label).unwrap();
         }
         writeln!(file, "}}").unwrap();
 
-        for i in relevant_neurons {
-            writeln!(file, "_{} [label=\"{}\", bgcolor=\"green\"];", i, i).unwrap();
+        // Biases are shown in the node labels; self-connections are drawn
+        // as ordinary edges.
+        for neuron in graph.neurons.iter().filter(|neuron| !FIXED_INDICES.contains(&neuron.index)) {
+            writeln!(file, "_{} [label=\"{}\\nb={:.2}\", bgcolor=\"green\"];", neuron.index, neuron.label, neuron.bias).unwrap();
         }
 
         writeln!(file, "subgraph cluster_outputs {{").unwrap();
         writeln!(file, "peripheries=0;").unwrap();
-        for i in OUTPUT_INDICES {
-            let output: Output = num::FromPrimitive::from_usize(i).unwrap();
-            writeln!(file, "_{} [label=\"OUT:{:?}\"]", i, output).unwrap();
+        for neuron in graph.neurons.iter().filter(|neuron| OUTPUT_INDICES.contains(&neuron.index)) {
+            writeln!(file, "_{} [label=\"{}\\nb={:.2}\"]", neuron.index, neuron.label, neuron.bias).unwrap();
         }
         writeln!(file, "}}").unwrap();
 
         writeln!(file, "}}").unwrap();
     }
-}
\ No newline at end of file
+}
+
+impl Lineage {
+    pub fn draw_graph(&self, file: &mut impl Write, selection: &HashSet<Uuid>) {
+        writeln!(file, "digraph {{").unwrap();
+        writeln!(file, "rankdir=\"LR\";").unwrap();
+
+        let mut pending: Vec<Uuid> = self.roots_within(selection).collect();
+        while let Some(id) = pending.pop() {
+            let node = self.node(id);
+            let color = if node.survived { "green" } else { "gray" };
+            let name = id.to_simple().to_string();
+            writeln!(file, "_{} [label=\"{}\\n{}\", color=\"{}\"];", name, node.generation, &name[..8], color).unwrap();
+
+            for child in self.children_within(id, selection) {
+                writeln!(file, "_{} -> _{};", name, child.to_simple()).unwrap();
+                pending.push(child);
+            }
+        }
+
+        writeln!(file, "}}").unwrap();
+    }
+}
+
+impl Runs {
+    pub fn draw_graph(&self, file: &mut impl Write) {
+        writeln!(file, "digraph {{").unwrap();
+        writeln!(file, "rankdir=\"LR\";").unwrap();
+
+        for run in self.runs() {
+            let name = run.id.to_simple().to_string();
+            let generations = match run.generations {
+                Some((first, last)) => format!("{}-{}", first, last),
+                None => "-".to_string(),
+            };
+            writeln!(file, "_{} [label=\"{}\\n{}\", shape=box];", name, &name[..8], generations).unwrap();
+
+            if let Some(origin) = run.origin() {
+                let label = match (run.revived_generation, run.reseeded_from) {
+                    (Some(generation), None) => format!("{}", generation),
+                    _ => "reseeded".to_string(),
+                };
+                writeln!(file, "_{} -> _{} [label=\"{}\"];", origin.to_simple(), name, label).unwrap();
+            }
+            for (origin, generation) in run.crossed_with() {
+                writeln!(file, "_{} -> _{} [label=\"{}\", style=dashed];", origin.to_simple(), name, generation).unwrap();
+            }
+        }
+
+        writeln!(file, "}}").unwrap();
+    }
+}
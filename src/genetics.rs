@@ -1,12 +1,59 @@
 //! Conceptual separation of genetics from brain weights, even
 //! though there is a one-to-one mapping.
 
+use std::ops::Range;
+
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 
-use super::brain::{NUM_NEURONS, Brain};
+use super::brain::{NUM_NEURONS, Brain, BrainLayout, BrainType, Controller};
+use super::ctrnn::Ctrnn;
+
+/// Connection weights, row-major by source neuron.
+pub const WEIGHT_CODONS: Range<usize> = 0..(NUM_NEURONS * NUM_NEURONS);
+/// Per-neuron time constants, as the logarithm of the time constant in
+/// seconds. Only used by the continuous-time brain.
+pub const TIME_CONSTANT_CODONS: Range<usize> = WEIGHT_CODONS.end..(WEIGHT_CODONS.end + NUM_NEURONS);
+
+/// Per-neuron biases.
+pub const BIAS_CODONS: Range<usize> = TIME_CONSTANT_CODONS.end..(TIME_CONSTANT_CODONS.end + NUM_NEURONS);
 
-pub const NUM_CODONS: usize = NUM_NEURONS * NUM_NEURONS;
+pub const NUM_CODONS: usize = BIAS_CODONS.end;
+
+/// Genome layout produced by this build, recorded in log headers.
+///
+/// 0. The diagonal of the weight matrix is used as bias; no self-connections.
+/// 1. Separate bias codons; the diagonal holds real self-connections.
+/// 2. Eight normalized sensor inputs inserted after the original seven,
+///    growing the network from 32 to 40 neurons.
+///
+/// Since then, which neuron is which is recorded separately as a
+/// `BrainLayout`; logs without one have `legacy_brain_layout`.
+pub const GENOME_LAYOUT: u32 = 2;
+
+/// Neuron count of layouts 0 and 1.
+const LEGACY_NEURONS: usize = 32;
+
+/// The brain layout of logs from before it was recorded.
+pub fn legacy_brain_layout(layout: u32) -> BrainLayout {
+    let names = |list: &[&str], first: usize| -> Vec<(String, usize)> {
+        list.iter().enumerate().map(|(i, name)| (name.to_string(), first + i)).collect()
+    };
+    let mut inputs = vec!["Constant", "Oscillator", "X", "Y", "SafeX", "SafeY", "SafeRadius"];
+    let neurons = if layout < 2 {
+        LEGACY_NEURONS
+    }
+    else {
+        inputs.extend(["ZoneDX", "ZoneDY", "ZoneEdge", "WallDistance", "Sin0", "Cos0", "Sin1", "Cos1"]);
+        40
+    };
+
+    BrainLayout {
+        neurons,
+        outputs: names(&["SpeedX", "SpeedY"], inputs.len()),
+        inputs: names(&inputs, 0),
+    }
+}
 
 pub type Genome = [f32; NUM_CODONS];
 
@@ -17,7 +64,7 @@ pub fn randomize() -> Genome {
 }
 
 pub fn mutate(genome: &mut Genome, rate: f32, strength: f32) {
-    let mut rng = rand::thread_rng();
+    let mut rng = super::random::rng();
     for i in 0..NUM_CODONS {
         if rng.gen::<f32>() < rate {
             genome[i] += rng.sample::<f32, _>(rand_distr::StandardNormal) * strength;
@@ -25,12 +72,123 @@ pub fn mutate(genome: &mut Genome, rate: f32, strength: f32) {
     }
 }
 
+/// Convert a logged genome from an older layout into the current one.
+/// Inputs the genome has never seen get zero weights, and inputs that no
+/// longer exist are dropped.
+pub fn migrate(genome: &mut Vec<f32>, layout: u32, brain_layout: Option<&BrainLayout>) {
+    if layout < 1 {
+        // Missing codon ranges default to zero.
+        let n = LEGACY_NEURONS;
+        genome.resize(n * n + 2 * n, 0.0);
+
+        // Move the diagonal into the bias codons, leaving no self-connections.
+        for j in 0..n {
+            let diagonal = j * n + j;
+            genome[n * n + n + j] = genome[diagonal];
+            genome[diagonal] = 0.0;
+        }
+    }
+
+    let from = brain_layout.cloned().unwrap_or_else(|| legacy_brain_layout(layout));
+    let to = BrainLayout::current();
+    if from != to {
+        let map = from.map_onto(&to);
+        genome.resize(from.neurons * from.neurons + 2 * from.neurons, 0.0);
+        *genome = remap_neurons(genome, from.neurons, to.neurons, |k| map[k]);
+    }
+
+    genome.resize(NUM_CODONS, 0.0);
+}
+
+/// Rebuild a genome of `from` neurons as one of `to` neurons, moving neuron
+/// `k` to `map(k)`, or dropping it if that's `None`. Neurons that nothing
+/// maps to get all-zero codons, so they neither affect nor are affected by
+/// the rest of the network.
+pub fn remap_neurons(genome: &[f32], from: usize, to: usize, map: impl Fn(usize) -> Option<usize>) -> Vec<f32> {
+    let mut remapped = vec![0.0; to * to + 2 * to];
+    for i in 0..from {
+        let Some(to_i) = map(i) else { continue };
+        for j in 0..from {
+            if let Some(to_j) = map(j) {
+                remapped[to_i * to + to_j] = genome[i * from + j];
+            }
+        }
+        remapped[to * to + to_i] = genome[from * from + i];
+        remapped[to * to + to + to_i] = genome[from * from + from + i];
+    }
+    remapped
+}
+
 pub fn create_brain(genome: &Genome) -> Brain {
     let mut brain = Brain::new();
     for i in 0..NUM_NEURONS {
         for j in 0..NUM_NEURONS {
-            brain.weights[i][j] = genome[i * NUM_NEURONS + j];
+            brain.weights[i][j] = genome[WEIGHT_CODONS.start + i * NUM_NEURONS + j];
         }
+        brain.biases[i] = genome[BIAS_CODONS.start + i];
     }
     brain
 }
+
+pub fn create_ctrnn(genome: &Genome) -> Ctrnn {
+    let mut ctrnn = Ctrnn::new();
+    for i in 0..NUM_NEURONS {
+        for j in 0..NUM_NEURONS {
+            ctrnn.weights[i][j] = genome[WEIGHT_CODONS.start + i * NUM_NEURONS + j];
+        }
+        ctrnn.biases[i] = genome[BIAS_CODONS.start + i];
+        ctrnn.time_constants[i] = genome[TIME_CONSTANT_CODONS.start + i].exp();
+    }
+    ctrnn
+}
+
+pub fn create_controller(genome: &Genome, brain_type: BrainType) -> Box<dyn Controller> {
+    match brain_type {
+        BrainType::Discrete => Box::new(create_brain(genome)),
+        BrainType::Ctrnn => Box::new(create_ctrnn(genome)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Index of the neuron named `name` in `layout`.
+    fn neuron(layout: &BrainLayout, name: &str) -> usize {
+        layout.inputs.iter().chain(&layout.outputs).find(|(n, _)| n == name).unwrap().1
+    }
+
+    #[test]
+    fn migrate_layout_1() {
+        let from = legacy_brain_layout(1);
+        let n = from.neurons;
+        let (safe_x, speed_x, speed_y) = (neuron(&from, "SafeX"), neuron(&from, "SpeedX"), neuron(&from, "SpeedY"));
+        let hidden = from.hidden()[0];
+
+        let mut genome = vec![0.0; n * n + 2 * n];
+        genome[safe_x * n + speed_x] = 1.5;
+        genome[hidden * n + speed_y] = 0.25;
+        genome[n * n + speed_y] = 2.0;
+        genome[n * n + n + hidden] = -0.75;
+        migrate(&mut genome, 1, None);
+
+        let to = BrainLayout::current();
+        let n = to.neurons;
+        let (safe_x, speed_x, speed_y) = (neuron(&to, "SafeX"), neuron(&to, "SpeedX"), neuron(&to, "SpeedY"));
+        let hidden = to.hidden()[0];
+        assert_eq!(genome.len(), NUM_CODONS);
+        assert_eq!(genome[WEIGHT_CODONS.start + safe_x * n + speed_x], 1.5);
+        assert_eq!(genome[WEIGHT_CODONS.start + hidden * n + speed_y], 0.25);
+        assert_eq!(genome[TIME_CONSTANT_CODONS.start + speed_y], 2.0);
+        assert_eq!(genome[BIAS_CODONS.start + hidden], -0.75);
+        assert_eq!(genome.iter().filter(|codon| **codon != 0.0).count(), 4);
+    }
+
+    #[test]
+    fn migrate_current_layout() {
+        let mut genome = randomize().to_vec();
+        let original = genome.clone();
+        migrate(&mut genome, GENOME_LAYOUT, Some(&BrainLayout::current()));
+        assert_eq!(genome, original);
+    }
+}
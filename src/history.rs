@@ -1,50 +1,166 @@
-use std::io::Write;
+use std::io::{BufRead, Write};
 
-use log::{warn, info, debug};
+use log::{warn, debug};
 use serde::{Serialize, Deserialize};
 use rand::{Rng, prelude::SliceRandom};
 use uuid::Uuid;
 
-use crate::genetics::NUM_CODONS;
+use crate::brain::{BrainLayout, BrainType};
+use crate::log_format::{self, LogFormat, Record};
+use crate::log_index::{self, IndexEntry, LogIndex};
+use crate::log_reader::{Generation, LogReader, LogError};
+use crate::revive::Origin;
+use crate::sink::Sink;
+use crate::stats::GenerationStats;
+use crate::trajectory::ZonePlacement;
+use crate::genetics::{NUM_CODONS, GENOME_LAYOUT};
 
-use super::Agent;
+use super::{Agent, Zone};
 
 pub struct History {
     header: Header,
-    write: Box<dyn Write>
+    format: LogFormat,
+    write: CountingWriter<Box<dyn Write>>,
+    index: CountingWriter<Box<dyn Write>>,
+    /// Statistics as CSV, for following a run as it goes.
+    stats: CountingWriter<Box<dyn Write>>,
+    /// Number of records written, including the header.
+    records: usize,
+}
+
+/// Keeps track of how many bytes have passed through, to index the log.
+struct CountingWriter<W> {
+    inner: W,
+    bytes: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.bytes += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// How much of each stream has been written, to resume a log.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LogPosition {
+    pub bytes: u64,
+    pub records: usize,
+    pub index_bytes: u64,
+    pub stats_bytes: u64,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct AgentEntry {
-    id: Uuid,
-    parent: Option<Uuid>,
-    survived: bool,
-    genome: Vec<f32>,
+    pub id: Uuid,
+    pub parent: Option<Uuid>,
+    pub survived: bool,
+    pub genome: Vec<f32>,
+    /// The generation the agent was judged in. `None` in older logs and for
+    /// founders that haven't been simulated.
+    #[serde(default)]
+    pub generation: Option<usize>,
+    #[serde(default)]
+    pub outcome: Option<Outcome>,
+}
+
+/// How an agent fared in its generation, for analysing near misses and
+/// evaluating other fitness functions offline.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Outcome {
+    pub position: (f32, f32),
+    /// Distance to the center of the final zone.
+    pub zone_distance: f32,
+    /// Simulation time at which the agent first was inside a zone.
+    pub first_entry: Option<f32>,
+    /// Seconds spent inside a zone.
+    pub time_in_zone: f32,
+    pub path_length: f32,
 }
 
+/// Version of the log schema written by this build.
+///
+/// 0. No schema version or brain layout in the header.
+/// 1. Schema version and brain layout in the header.
+pub const SCHEMA_VERSION: u32 = 1;
+
 #[derive(Serialize, Deserialize)]
 pub struct Header {
+    #[serde(default)]
+    pub schema: u32,
     pub id: Uuid,
     pub revived_from: Option<Uuid>,
     pub revived_generation: Option<usize>,
+    /// Layout of the genomes in this log; see `genetics::GENOME_LAYOUT`.
+    #[serde(default)]
+    pub genome_layout: u32,
+    /// The run that went extinct, if this one was reseeded from the same
+    /// agents.
+    #[serde(default)]
+    pub reseeded_from: Option<Uuid>,
+    /// Seconds since the Unix epoch.
+    #[serde(default)]
+    pub started: Option<u64>,
+    /// Which neuron is which in the genomes; see `genetics::migrate`.
+    #[serde(default)]
+    pub brain_layout: Option<BrainLayout>,
+    /// Every source of a revived founding population. `revived_from` and
+    /// `revived_generation` are those of the first.
+    #[serde(default)]
+    pub origins: Vec<Origin>,
+}
+
+fn now() -> Option<u64> {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).ok().map(|time| time.as_secs())
 }
 
 impl Header {
     pub fn new() -> Header {
         Header {
+            schema: SCHEMA_VERSION,
             id: uuid::Uuid::new_v4(),
             revived_from: None,
             revived_generation: None,
+            genome_layout: GENOME_LAYOUT,
+            reseeded_from: None,
+            started: now(),
+            brain_layout: Some(BrainLayout::current()),
+            origins: vec![],
         }
     }
 
+    /// A header for a new run started from the same agents.
     pub fn clone(&self) -> Header {
         Header {
-            id: uuid::Uuid::new_v4(),
             revived_from: self.revived_from,
             revived_generation: self.revived_generation,
+            reseeded_from: self.reseeded_from,
+            origins: self.origins.clone(),
+            ..Header::new()
         }
     }
+
+    /// An exact copy, unlike `clone`.
+    pub fn copy(&self) -> Header {
+        Header {
+            schema: self.schema,
+            id: self.id,
+            genome_layout: self.genome_layout,
+            started: self.started,
+            brain_layout: self.brain_layout.clone(),
+            ..self.clone()
+        }
+    }
+
+    /// Convert a genome from this log into the layout of this build.
+    pub fn migrate(&self, genome: &mut Vec<f32>) {
+        super::genetics::migrate(genome, self.genome_layout, self.brain_layout.as_ref());
+    }
 }
 
 impl Agent {
@@ -54,21 +170,41 @@ impl Agent {
             parent: self.parent,
             genome: self.genome.to_vec(),
             survived,
+            generation: None,
+            outcome: None,
+        }
+    }
+
+    /// An entry for the end of `generation`, judged against the final zone.
+    pub fn judge(&self, generation: usize, zone: &Zone) -> AgentEntry {
+        AgentEntry {
+            generation: Some(generation),
+            outcome: Some(Outcome {
+                position: self.position,
+                zone_distance: zone.distance(self.position),
+                ..self.outcome.clone()
+            }),
+            ..self.to_log_entry(zone.contains(self.position))
         }
     }
 
-    pub fn from_log_entry(entry: AgentEntry) -> Agent {
+    pub fn from_log_entry(entry: AgentEntry, brain_type: BrainType) -> Agent {
+        // Logs from before a codon range was added have shorter genomes;
+        // fill in the missing codons with zeroes.
         let mut genome = [0.0; NUM_CODONS];
         for i in 0..NUM_CODONS {
-            genome[i] = entry.genome[i];
+            genome[i] = entry.genome.get(i).copied().unwrap_or(0.0);
         }
 
-        let mut rng = rand::thread_rng();
+        let mut rng = super::random::rng();
         Agent {
             uuid: entry.id,
             parent: entry.parent,
-            brain: super::genetics::create_brain(&genome),
+            brain: super::genetics::create_controller(&genome, brain_type),
             genome,
+            sensor_delay: super::sensors::DelayLine::new(),
+            motion: super::physics::Motion::random(),
+            outcome: Outcome::default(),
             position: (
                 (rng.gen::<f32>() * 2.0 - 1.0) * 300.0,
                 (rng.gen::<f32>() * 2.0 - 1.0) * 300.0,
@@ -78,107 +214,173 @@ impl Agent {
 }
 
 impl History {
-    pub fn new(header: Header) -> History {
-        let header = Header::new();
+    /// Start a log of a new run in `log`, streaming statistics to `stats`.
+    pub fn new(header: Header, format: LogFormat, log: &Sink, stats: &Sink) -> std::io::Result<History> {
+        let name = format!("{}.log", header.id);
+        let mut write = CountingWriter {
+            inner: log.open(&name)?,
+            bytes: 0,
+        };
+        log_format::write_header(&mut write, format, &header)?;
+        let index = CountingWriter {
+            inner: log.open_companion(&log_index::index_path(&name))?,
+            bytes: 0,
+        };
 
-        let mut write = std::fs::File::create(format!("output/{}.log", header.id)).unwrap();
-        writeln!(write, "{}", serde_json::to_string(&header).unwrap()).unwrap();
+        let mut stats = CountingWriter {
+            inner: stats.open(&format!("{}.stats.csv", header.id))?,
+            bytes: 0,
+        };
+        GenerationStats::write_csv_header(&mut stats)?;
 
-        History {
+        Ok(History {
             header,
-            write: Box::new(write),
+            format,
+            write,
+            index,
+            stats,
+            records: 1,
+        })
+    }
+
+    /// Continue a log from `position`, discarding anything written after it.
+    pub fn resume(header: Header, format: LogFormat, log: &Sink, stats: &Sink, position: &LogPosition) -> std::io::Result<History> {
+        let name = format!("{}.log", header.id);
+        let mut write = CountingWriter {
+            inner: log.append(&name, position.bytes)?,
+            bytes: position.bytes,
+        };
+        if log.directory().is_none() {
+            warn!("log isn't in a directory, continuing it in a new stream");
+            log_format::write_header(&mut write, format, &header)?;
         }
+        let index = CountingWriter {
+            inner: match log.directory() {
+                Some(_) => log.append(&log_index::index_path(&name), position.index_bytes)?,
+                None => log.open_companion(&log_index::index_path(&name))?,
+            },
+            bytes: position.index_bytes,
+        };
+
+        let mut stats_write = CountingWriter {
+            inner: stats.append(&format!("{}.stats.csv", header.id), position.stats_bytes)?,
+            bytes: position.stats_bytes,
+        };
+        if stats.directory().is_none() {
+            GenerationStats::write_csv_header(&mut stats_write)?;
+        }
+
+        Ok(History {
+            header,
+            format,
+            write,
+            index,
+            stats: stats_write,
+            records: position.records,
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Flush everything and return how far each stream has got.
+    pub fn position(&mut self) -> LogPosition {
+        self.write.flush().unwrap();
+        self.index.flush().unwrap();
+        self.stats.flush().unwrap();
+        LogPosition {
+            bytes: self.write.bytes,
+            records: self.records,
+            index_bytes: self.index.bytes,
+            stats_bytes: self.stats.bytes,
+        }
+    }
+
+    /// Id of the run being logged.
+    pub fn id(&self) -> Uuid {
+        self.header.id
     }
 
     pub fn log_generation(&mut self, number: usize, settings: &super::Settings) {
         // Make sure last generation is flushed
         self.write.flush().unwrap();
-        writeln!(&mut self.write, ":{} {}", number, serde_json::to_string(&settings).unwrap()).unwrap();
+
+        let entry = IndexEntry {
+            generation: number,
+            offset: self.write.bytes,
+            record: self.records + 1,
+        };
+        entry.write(&mut self.index).unwrap();
+        self.index.flush().unwrap();
+
+        self.write_record(&Record::Generation(number, settings.clone()));
+    }
+
+    pub fn log_zone(&mut self, placement: ZonePlacement) {
+        self.write_record(&Record::Zone(placement));
     }
 
     pub fn log_agent(&mut self, entry: AgentEntry) {
-        writeln!(&mut self.write, "{}", serde_json::to_string(&entry).unwrap()).unwrap();
+        self.write_record(&Record::Agent(entry));
+    }
+
+    pub fn log_stats(&mut self, stats: GenerationStats) {
+        stats.write_csv(&mut self.stats).unwrap();
+        self.stats.flush().unwrap();
+        self.write_record(&Record::Stats(stats));
+    }
+
+    fn write_record(&mut self, record: &Record) {
+        log_format::write_record(&mut self.write, self.format, record).unwrap();
+        self.records += 1;
     }
     
-    pub fn revive(path: &str, generation: Option<usize>) -> (Vec<Agent>, super::Settings, Header) {
-        use std::io::BufRead;
-        info!("reviving {}, generation {:?}...", path, generation);
-
-        // Awful, sorry.
-        let file = std::fs::File::open(path).unwrap();
-        let lines = std::io::BufReader::new(file).lines();
-        let mut header: Option<Header> = None;
-        let mut settings: Option<super::Settings> = None;
-        let mut agents: Vec<AgentEntry> = vec![];
-
-        let mut last_gen_agents: Vec<AgentEntry> = vec![];
-        let mut last_gen_settings = None;
-        let mut last_gen = 0;
-
-        for line in lines {
-            if let Ok(line) = line {
-                if header.is_none() {
-                    let parent_header = Some(serde_json::from_str::<Header>(&line).unwrap()).unwrap();
-                    header = Some(Header {
-                        id: Uuid::new_v4(),
-                        revived_from: Some(parent_header.id),
-                        revived_generation: Some(0),
-                    });
-                }
-                else if line.starts_with(":") {
-                    if let Some(settings) = &settings {
-                        if agents.len() != settings.num_agents {
-                            warn!("missing agents in log");
-                        }
-                    }
-
-                    let sep = line.find(" ").unwrap();
-                    let next_generation = *&line[1..sep].parse::<usize>().unwrap();
-                    let next_settings = serde_json::from_str::<super::Settings>(&line[(sep+1)..]).unwrap();
-                    debug!("loading generation {}", next_generation);
-
-                    if let Some(generation) = generation {
-                        if generation < next_generation {
-                            debug!("found generation {}", generation);
-                            // We've found the generation we're looking for.
-                            break;
-                        }
-                    }
-                    // Else, set as current
-                    last_gen_agents = agents;
-                    agents = vec![];
-                    last_gen_settings = settings;
-                    settings = Some(next_settings);
-                    last_gen = header.as_ref().unwrap().revived_generation.unwrap();
-                    header.as_mut().unwrap().revived_generation = Some(next_generation);
-                }
-                else {
-                    // Read agent!
-                    agents.push(serde_json::from_str::<AgentEntry>(&line).unwrap());
-                }
-            }
-            else {
+    /// Load the last complete generation up to `generation`, or the last
+    /// complete one if `None`, along with the header of the log.
+    pub fn load_generation(path: &str, generation: Option<usize>) -> Result<(Generation, Header), LogError> {
+        let index = LogIndex::open(path)?;
+
+        // Only look at the last two generations up to the requested one, so
+        // that a partially written last generation can fall back to the one
+        // before it. If neither is complete, search the whole log.
+        let candidates: Vec<usize> = index.generations()
+            .filter(|number| generation.is_none_or(|generation| *number <= generation))
+            .collect();
+        let first = candidates.len().saturating_sub(2);
+        let reader = index.reader_at(candidates.get(first).copied().unwrap_or(0))?;
+        let header = reader.header().copy();
+
+        let found = match History::find_generation(reader, generation) {
+            Some(found) => Some(found),
+            None if first > 0 => History::find_generation(index.reader_at(0)?, generation),
+            None => None,
+        };
+
+        let found = found.ok_or(LogError::NoGeneration(generation))?;
+        if generation.is_some_and(|generation| generation != found.number) {
+            warn!("generation {} not available, using generation {}", generation.unwrap(), found.number);
+        }
+        Ok((found, header))
+    }
+
+    /// Find the last complete generation up to `generation`, or in the whole
+    /// log if `None`.
+    fn find_generation<R: BufRead>(reader: LogReader<R>, generation: Option<usize>) -> Option<Generation> {
+        let mut found = None;
+        for next in reader.generations() {
+            if generation.is_some_and(|generation| next.number > generation) {
                 break;
             }
-        }
 
-        debug!("reached end of file");
-        // If we've loaded the correct number of agents, we're done;
-        // otherwise, we return the last generation that was complete.
-        if agents.len() != settings.as_ref().unwrap().num_agents {
-            debug!("generation incomplete");
-            agents = last_gen_agents;
-            settings = last_gen_settings;
-            header.as_mut().unwrap().revived_generation = Some(last_gen);
-        }
-        else {
-            debug!("loaded last generation");
+            debug!("loading generation {}", next.number);
+            if next.is_complete() {
+                found = Some(next);
+            }
+            else {
+                warn!("generation {} is incomplete ({} of {} agents)", next.number, next.agents.len(), next.settings.num_agents);
+            }
         }
-
-        (
-            agents.into_iter().map(|entry| Agent::from_log_entry(entry)).collect(),
-            settings.unwrap(),
-            header.unwrap(),
-        )
+        found
     }
-}
\ No newline at end of file
+}
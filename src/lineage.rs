@@ -0,0 +1,237 @@
+//! Ancestry of the agents in a log, rebuilt from their `parent` ids.
+
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+use uuid::Uuid;
+
+use super::log_reader::{LogError, LogReader};
+
+pub struct Node {
+    pub parent: Option<Uuid>,
+    pub generation: usize,
+    pub survived: bool,
+    pub children: Vec<Uuid>,
+}
+
+pub struct Lineage {
+    nodes: HashMap<Uuid, Node>,
+    /// Agents of every generation, in log order.
+    generations: HashMap<usize, Vec<Uuid>>,
+    /// Agents whose parent isn't in the log, in log order.
+    roots: Vec<Uuid>,
+}
+
+impl Lineage {
+    pub fn load(path: &str) -> Result<Lineage, LogError> {
+        let mut nodes = HashMap::new();
+        let mut generations = HashMap::new();
+        let mut order = vec![];
+
+        for generation in LogReader::open(path)?.generations() {
+            let ids = generations.entry(generation.number).or_insert_with(Vec::new);
+            for agent in generation.agents {
+                ids.push(agent.id);
+                order.push(agent.id);
+                nodes.insert(agent.id, Node {
+                    parent: agent.parent,
+                    generation: generation.number,
+                    survived: agent.survived,
+                    children: vec![],
+                });
+            }
+        }
+
+        let mut roots = vec![];
+        for id in order {
+            match nodes[&id].parent.filter(|parent| nodes.contains_key(parent)) {
+                Some(parent) => nodes.get_mut(&parent).unwrap().children.push(id),
+                None => roots.push(id),
+            }
+        }
+
+        Ok(Lineage {
+            nodes,
+            generations,
+            roots,
+        })
+    }
+
+    pub fn node(&self, id: Uuid) -> &Node {
+        &self.nodes[&id]
+    }
+
+    /// The agent itself, its parent, and so on up to its founder.
+    pub fn ancestry(&self, id: Uuid) -> Vec<Uuid> {
+        let mut ancestry = vec![];
+        let mut next = Some(id);
+        while let Some(id) = next.filter(|id| self.nodes.contains_key(id)) {
+            ancestry.push(id);
+            next = self.nodes[&id].parent;
+        }
+        ancestry
+    }
+
+    pub fn survivors(&self, generation: usize) -> Vec<Uuid> {
+        self.generations.get(&generation)
+            .map(|ids| ids.iter().copied().filter(|id| self.nodes[id].survived).collect())
+            .unwrap_or_default()
+    }
+
+    /// The most recent agent that all of `ids` descend from (or are).
+    pub fn common_ancestor(&self, ids: &[Uuid]) -> Option<Uuid> {
+        let (first, rest) = ids.split_first()?;
+        let others: Vec<HashSet<Uuid>> = rest.iter()
+            .map(|id| self.ancestry(*id).into_iter().collect())
+            .collect();
+        self.ancestry(*first).into_iter()
+            .find(|ancestor| others.iter().all(|ancestry| ancestry.contains(ancestor)))
+    }
+
+    /// Number of agents descending from `id`, not counting itself.
+    pub fn descendants(&self, id: Uuid) -> usize {
+        let mut count = 0;
+        let mut pending = vec![id];
+        while let Some(id) = pending.pop() {
+            if let Some(node) = self.nodes.get(&id) {
+                count += node.children.len();
+                pending.extend(&node.children);
+            }
+        }
+        count
+    }
+
+    /// The agents to include in an exported tree: everything, or only the
+    /// ancestry of the survivors of `generation`.
+    pub fn select(&self, generation: Option<usize>) -> HashSet<Uuid> {
+        match generation {
+            None => self.nodes.keys().copied().collect(),
+            Some(generation) => self.survivors(generation).into_iter()
+                .flat_map(|id| self.ancestry(id))
+                .collect(),
+        }
+    }
+
+    /// Roots and children restricted to `selection`, in log order.
+    pub fn roots_within<'a>(&'a self, selection: &'a HashSet<Uuid>) -> impl Iterator<Item = Uuid> + 'a {
+        self.roots.iter().copied().filter(move |id| selection.contains(id))
+    }
+
+    pub fn children_within<'a>(&'a self, id: Uuid, selection: &'a HashSet<Uuid>) -> impl Iterator<Item = Uuid> + 'a {
+        self.nodes[&id].children.iter().copied().filter(move |id| selection.contains(id))
+    }
+
+    /// Write the selected agents as a Newick tree, with a branch length of
+    /// one generation. Several founders are joined under an unnamed root.
+    pub fn write_newick(&self, file: &mut impl Write, selection: &HashSet<Uuid>) -> std::io::Result<()> {
+        // Iterative, since trees are as deep as the run is long.
+        enum Step {
+            Open(Uuid),
+            Close(Uuid),
+            Separator,
+        }
+
+        let roots: Vec<Uuid> = self.roots_within(selection).collect();
+        let mut steps = vec![];
+        for (i, root) in roots.iter().enumerate().rev() {
+            steps.push(Step::Open(*root));
+            if i > 0 {
+                steps.push(Step::Separator);
+            }
+        }
+
+        if roots.len() > 1 { write!(file, "(")?; }
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Open(id) => {
+                    let children: Vec<Uuid> = self.children_within(id, selection).collect();
+                    steps.push(Step::Close(id));
+                    if !children.is_empty() {
+                        write!(file, "(")?;
+                        for (i, child) in children.iter().enumerate().rev() {
+                            steps.push(Step::Open(*child));
+                            if i > 0 {
+                                steps.push(Step::Separator);
+                            }
+                        }
+                    }
+                }
+                Step::Close(id) => {
+                    if self.children_within(id, selection).next().is_some() {
+                        write!(file, ")")?;
+                    }
+                    write!(file, "{}:1", id)?;
+                }
+                Step::Separator => write!(file, ",")?,
+            }
+        }
+        if roots.len() > 1 { write!(file, ")")?; }
+        writeln!(file, ";")
+    }
+}
+
+/// Print the answer to a lineage query about a log.
+pub fn query(path: &str, query: Query) -> Result<(), LogError> {
+    let lineage = Lineage::load(path)?;
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+
+    if let Query::Trace { id } | Query::Descendants { id } = query {
+        if !lineage.nodes.contains_key(&id) {
+            return Err(LogError::UnknownAgent(id));
+        }
+    }
+
+    match query {
+        Query::Trace { id } => {
+            for ancestor in lineage.ancestry(id) {
+                println!("{} {}", lineage.node(ancestor).generation, ancestor);
+            }
+        }
+        Query::Mrca { generation } => {
+            let survivors = lineage.survivors(generation);
+            match lineage.common_ancestor(&survivors) {
+                Some(ancestor) => println!(
+                    "{} {} ({} survivors)",
+                    lineage.node(ancestor).generation,
+                    ancestor,
+                    survivors.len(),
+                ),
+                None => println!("no common ancestor of {} survivors", survivors.len()),
+            }
+        }
+        Query::Descendants { id } => println!("{}", lineage.descendants(id)),
+        Query::Newick { generation } => lineage.write_newick(&mut stdout, &lineage.select(generation))?,
+        Query::Dot { generation } => lineage.draw_graph(&mut stdout, &lineage.select(generation)),
+    }
+
+    Ok(())
+}
+
+#[derive(clap::Subcommand)]
+pub enum Query {
+    /// Trace an agent back to its founder.
+    Trace {
+        id: Uuid,
+    },
+    /// Find the most recent common ancestor of a generation's survivors.
+    Mrca {
+        generation: usize,
+    },
+    /// Count the descendants of an agent.
+    Descendants {
+        id: Uuid,
+    },
+    /// Export the ancestry tree in Newick format.
+    Newick {
+        /// Only include the ancestry of this generation's survivors.
+        #[clap(long)]
+        generation: Option<usize>,
+    },
+    /// Export the ancestry tree as a Graphviz graph.
+    Dot {
+        /// Only include the ancestry of this generation's survivors.
+        #[clap(long)]
+        generation: Option<usize>,
+    },
+}
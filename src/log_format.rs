@@ -0,0 +1,423 @@
+//! Encoding of log records.
+//!
+//! Logs come in two formats with the same content:
+//!
+//! - JSON: a header line, then one line per record. Generation markers are
+//!   written as `:<number> <settings>`, agent entries as plain JSON objects,
+//!   and other records as `#<kind> <json>`.
+//! - Binary: `EVOLOG`, a little-endian `u16` format version, then records,
+//!   each a `u8` record type and a little-endian `u32` payload length
+//!   followed by the payload. Genomes are stored as little-endian `f32`s,
+//!   followed by the optional generation and outcome of the agent;
+//!   the header and settings, which are small and change shape over time,
+//!   are stored as JSON.
+//!
+//! Readers skip record types they don't know, binary or `#<kind>`, so that
+//! newer builds can add records without breaking older readers.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use log::{info, warn};
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+
+use super::history::{AgentEntry, Header, Outcome};
+use super::log_index::LogIndex;
+use super::log_reader::{LogError, LogReader, Position};
+use super::stats::GenerationStats;
+use super::trajectory::ZonePlacement;
+use super::Settings;
+
+pub const MAGIC: &[u8; 6] = b"EVOLOG";
+pub const BINARY_VERSION: u16 = 1;
+/// Longest binary payload a reader accepts. Records are far smaller, so a
+/// longer length means the log is corrupt.
+pub const MAX_RECORD_LENGTH: usize = 1 << 24;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, clap::ArgEnum)]
+pub enum LogFormat {
+    Json,
+    Binary,
+}
+
+pub enum Record {
+    Generation(usize, Settings),
+    /// A zone placement, written before the agents of a generation.
+    Zone(ZonePlacement),
+    Agent(AgentEntry),
+    /// Written after the agents of a generation.
+    Stats(GenerationStats),
+}
+
+const TAG_HEADER: u8 = 0;
+const TAG_GENERATION: u8 = 1;
+pub const TAG_AGENT: u8 = 2;
+const TAG_STATS: u8 = 3;
+const TAG_ZONE: u8 = 4;
+
+/// Kinds of `#<kind>` JSON records, which are the same in binary logs.
+const NAMED_KINDS: &[&str] = &["stats", "zone"];
+
+/// Whether this build can decode binary records of type `tag`.
+pub fn is_known_tag(tag: u8) -> bool {
+    matches!(tag, TAG_GENERATION | TAG_AGENT | TAG_STATS | TAG_ZONE)
+}
+
+/// Whether this build can decode JSON records of kind `kind`.
+pub fn is_known_kind(kind: &str) -> bool {
+    NAMED_KINDS.contains(&kind)
+}
+
+fn write_binary_record(write: &mut dyn Write, tag: u8, payload: &[u8]) -> io::Result<()> {
+    write.write_all(&[tag])?;
+    write.write_all(&(payload.len() as u32).to_le_bytes())?;
+    write.write_all(payload)
+}
+
+pub fn write_header(write: &mut dyn Write, format: LogFormat, header: &Header) -> io::Result<()> {
+    let json = serde_json::to_string(header)?;
+    match format {
+        LogFormat::Json => writeln!(write, "{}", json),
+        LogFormat::Binary => {
+            write.write_all(MAGIC)?;
+            write.write_all(&BINARY_VERSION.to_le_bytes())?;
+            write_binary_record(write, TAG_HEADER, json.as_bytes())
+        }
+    }
+}
+
+pub fn write_record(write: &mut dyn Write, format: LogFormat, record: &Record) -> io::Result<()> {
+    match (format, record) {
+        (LogFormat::Json, Record::Generation(number, settings)) => {
+            writeln!(write, ":{} {}", number, serde_json::to_string(settings)?)
+        }
+        (LogFormat::Json, Record::Zone(placement)) => {
+            writeln!(write, "#zone {}", serde_json::to_string(placement)?)
+        }
+        (LogFormat::Json, Record::Agent(entry)) => {
+            writeln!(write, "{}", serde_json::to_string(entry)?)
+        }
+        (LogFormat::Json, Record::Stats(stats)) => {
+            writeln!(write, "#stats {}", serde_json::to_string(stats)?)
+        }
+        (LogFormat::Binary, Record::Generation(number, settings)) => {
+            let mut payload = (*number as u64).to_le_bytes().to_vec();
+            serde_json::to_writer(&mut payload, settings)?;
+            write_binary_record(write, TAG_GENERATION, &payload)
+        }
+        (LogFormat::Binary, Record::Zone(placement)) => {
+            write_binary_record(write, TAG_ZONE, &serde_json::to_vec(placement)?)
+        }
+        (LogFormat::Binary, Record::Agent(entry)) => {
+            write_binary_record(write, TAG_AGENT, &encode_agent(entry))
+        }
+        (LogFormat::Binary, Record::Stats(stats)) => {
+            write_binary_record(write, TAG_STATS, &serde_json::to_vec(stats)?)
+        }
+    }
+}
+
+fn encode_agent(entry: &AgentEntry) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(38 + entry.genome.len() * 4);
+    payload.extend_from_slice(entry.id.as_bytes());
+    payload.push(entry.parent.is_some() as u8);
+    payload.extend_from_slice(entry.parent.unwrap_or_else(Uuid::nil).as_bytes());
+    payload.push(entry.survived as u8);
+    payload.extend_from_slice(&(entry.genome.len() as u32).to_le_bytes());
+    for codon in &entry.genome {
+        payload.extend_from_slice(&codon.to_le_bytes());
+    }
+
+    // Appended later; older entries end after the genome.
+    payload.push(entry.generation.is_some() as u8);
+    payload.extend_from_slice(&(entry.generation.unwrap_or(0) as u64).to_le_bytes());
+    payload.push(entry.outcome.is_some() as u8);
+    if let Some(outcome) = &entry.outcome {
+        payload.push(outcome.first_entry.is_some() as u8);
+        for value in [
+            outcome.position.0,
+            outcome.position.1,
+            outcome.zone_distance,
+            outcome.first_entry.unwrap_or(0.0),
+            outcome.time_in_zone,
+            outcome.path_length,
+        ] {
+            payload.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    payload
+}
+
+/// Splits a payload into fields, failing if it runs out.
+struct Fields<'a> {
+    payload: &'a [u8],
+    at: Position,
+}
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], LogError> {
+        if self.payload.len() < length {
+            return Err(LogError::Malformed { at: self.at, reason: "record too short".to_string() });
+        }
+        let (field, rest) = self.payload.split_at(length);
+        self.payload = rest;
+        Ok(field)
+    }
+
+    fn uuid(&mut self) -> Result<Uuid, LogError> {
+        Ok(Uuid::from_slice(self.take(16)?).unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, LogError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, LogError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, LogError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Result<f32, LogError> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+fn decode_agent(fields: &mut Fields) -> Result<AgentEntry, LogError> {
+    let id = fields.uuid()?;
+    let has_parent = fields.u8()? != 0;
+    let parent = fields.uuid()?;
+    let survived = fields.u8()? != 0;
+    let length = fields.u32()? as usize;
+    let genome = (0..length).map(|_| fields.f32()).collect::<Result<Vec<f32>, LogError>>()?;
+
+    let mut generation = None;
+    let mut outcome = None;
+    if !fields.payload.is_empty() {
+        let has_generation = fields.u8()? != 0;
+        let number = fields.u64()? as usize;
+        generation = if has_generation { Some(number) } else { None };
+        if fields.u8()? != 0 {
+            let has_first_entry = fields.u8()? != 0;
+            let position = (fields.f32()?, fields.f32()?);
+            let zone_distance = fields.f32()?;
+            let first_entry = fields.f32()?;
+            outcome = Some(Outcome {
+                position,
+                zone_distance,
+                first_entry: if has_first_entry { Some(first_entry) } else { None },
+                time_in_zone: fields.f32()?,
+                path_length: fields.f32()?,
+            });
+        }
+    }
+
+    Ok(AgentEntry {
+        id,
+        parent: if has_parent { Some(parent) } else { None },
+        survived,
+        genome,
+        generation,
+        outcome,
+    })
+}
+
+pub fn decode_binary_header(tag: u8, payload: &[u8], at: Position) -> Result<Header, LogError> {
+    if tag != TAG_HEADER {
+        return Err(LogError::Malformed { at, reason: format!("expected header, found record type {}", tag) });
+    }
+    serde_json::from_slice(payload).map_err(|error| LogError::Header { at, error })
+}
+
+pub fn decode_binary(tag: u8, payload: &[u8], at: Position) -> Result<Record, LogError> {
+    let mut fields = Fields { payload, at };
+    match tag {
+        TAG_GENERATION => {
+            let number = fields.u64()? as usize;
+            let settings = serde_json::from_slice(fields.payload)
+                .map_err(|error| LogError::Settings { at, error })?;
+            Ok(Record::Generation(number, settings))
+        }
+        TAG_AGENT => decode_agent(&mut fields).map(Record::Agent),
+        TAG_STATS => decode_named("stats", payload, at),
+        TAG_ZONE => decode_named("zone", payload, at),
+        _ => Err(LogError::Malformed { at, reason: format!("unknown record type {}", tag) }),
+    }
+}
+
+/// Decode a record stored as JSON, other than agent entries and settings.
+fn decode_named(kind: &str, json: &[u8], at: Position) -> Result<Record, LogError> {
+    let malformed = |error: serde_json::Error| LogError::Malformed { at, reason: format!("{}: {}", kind, error) };
+    match kind {
+        "stats" => serde_json::from_slice(json).map(Record::Stats).map_err(malformed),
+        "zone" => serde_json::from_slice(json).map(Record::Zone).map_err(malformed),
+        _ => Err(LogError::Malformed { at, reason: format!("unknown record type {}", kind) }),
+    }
+}
+
+pub fn decode_json(line: &str, at: Position) -> Result<Record, LogError> {
+    if let Some(marker) = line.strip_prefix(':') {
+        let (number, settings) = marker.split_once(' ')
+            .ok_or_else(|| LogError::Malformed { at, reason: "generation marker without settings".to_string() })?;
+        let number = number.parse::<usize>()
+            .map_err(|error| LogError::Malformed { at, reason: format!("generation number: {}", error) })?;
+        let settings = serde_json::from_str(settings)
+            .map_err(|error| LogError::Settings { at, error })?;
+        Ok(Record::Generation(number, settings))
+    }
+    else if let Some(named) = line.strip_prefix('#') {
+        let (kind, json) = named.split_once(' ')
+            .ok_or_else(|| LogError::Malformed { at, reason: "record without content".to_string() })?;
+        decode_named(kind, json.as_bytes(), at)
+    }
+    else {
+        serde_json::from_str(line)
+            .map(Record::Agent)
+            .map_err(|error| LogError::Agent { at, error })
+    }
+}
+
+/// Rewrite a log in another format. Corrupt records are dropped.
+pub fn convert(input: &str, output: &str, format: LogFormat) -> Result<(), LogError> {
+    let reader = LogReader::open(input)?;
+    info!("converting {} ({:?}) to {} ({:?})", input, reader.format(), output, format);
+
+    let mut write = BufWriter::new(File::create(output)?);
+    write_header(&mut write, format, reader.header())?;
+
+    let mut records = 0;
+    for record in reader {
+        match record {
+            Ok(record) => {
+                write_record(&mut write, format, &record)?;
+                records += 1;
+            }
+            Err(error) => warn!("dropping record: {}", error),
+        }
+    }
+
+    write.flush()?;
+    info!("converted {} records", records);
+    LogIndex::rebuild(output)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{Agent, SimulationMode, Zone};
+
+    fn entries() -> Vec<AgentEntry> {
+        vec![
+            AgentEntry {
+                id: Uuid::new_v4(),
+                parent: Some(Uuid::new_v4()),
+                survived: true,
+                genome: vec![0.5, -1.25, 3.0],
+                generation: Some(7),
+                outcome: Some(Outcome {
+                    position: (1.0, -2.0),
+                    zone_distance: 3.5,
+                    first_entry: Some(4.25),
+                    time_in_zone: 5.0,
+                    path_length: 6.5,
+                }),
+            },
+            AgentEntry {
+                id: Uuid::new_v4(),
+                parent: None,
+                survived: false,
+                genome: vec![],
+                generation: None,
+                outcome: Some(Outcome::default()),
+            },
+        ]
+    }
+
+    fn json(value: &impl Serialize) -> serde_json::Value {
+        serde_json::to_value(value).unwrap()
+    }
+
+    fn settings() -> Settings {
+        Settings {
+            world_radius: 500.0,
+            title: "".to_string(),
+            num_agents: 2,
+            zone: None,
+            mutation_rate: 0.03,
+            mutation_strength: 0.25,
+            frame_interval: 33,
+            time_step: 0.05,
+            generation_time: 50.0,
+            mode: SimulationMode::SafeZoneRace { radius_low: 50.0, radius_high: 100.0 },
+            brain: Default::default(),
+            sensors: Default::default(),
+            noise: Default::default(),
+            movement: Default::default(),
+        }
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let header = Header::new();
+        let settings = settings();
+        let zone = Zone { x: 10.0, y: -20.0, radius: 75.0 };
+        let placement = ZonePlacement { time: 25.0, zone: zone.clone() };
+        let agents = [Agent::new(settings.brain), Agent::new(settings.brain)];
+        let stats = GenerationStats::compute(3, &agents, &zone, 1000, std::time::Duration::from_secs(2));
+        let entries = entries();
+
+        let mut log = vec![];
+        write_header(&mut log, LogFormat::Binary, &header).unwrap();
+        write_record(&mut log, LogFormat::Binary, &Record::Generation(3, settings.clone())).unwrap();
+        write_record(&mut log, LogFormat::Binary, &Record::Zone(placement.clone())).unwrap();
+        // A record type from a newer build, which readers skip.
+        write_binary_record(&mut log, 9, b"unknown").unwrap();
+        for entry in &entries {
+            write_record(&mut log, LogFormat::Binary, &Record::Agent(entry.clone())).unwrap();
+        }
+        write_record(&mut log, LogFormat::Binary, &Record::Stats(stats.clone())).unwrap();
+
+        let mut reader = LogReader::new(log.as_slice()).unwrap();
+        assert_eq!(reader.format(), LogFormat::Binary);
+        assert_eq!(json(reader.header()), json(&header));
+        match reader.next() {
+            Some(Ok(Record::Generation(number, decoded))) => {
+                assert_eq!(number, 3);
+                assert_eq!(json(&decoded), json(&settings));
+            }
+            _ => panic!("expected a generation"),
+        }
+        match reader.next() {
+            Some(Ok(Record::Zone(decoded))) => assert_eq!(json(&decoded), json(&placement)),
+            _ => panic!("expected a zone placement"),
+        }
+        for expected in entries.iter().map(json) {
+            match reader.next() {
+                Some(Ok(Record::Agent(entry))) => assert_eq!(json(&entry), expected),
+                _ => panic!("expected an agent entry"),
+            }
+        }
+        match reader.next() {
+            Some(Ok(Record::Stats(decoded))) => assert_eq!(json(&decoded), json(&stats)),
+            _ => panic!("expected stats"),
+        }
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn agents_without_outcome() {
+        // Entries from before the generation and outcome were appended.
+        let entry = AgentEntry { generation: None, outcome: None, ..entries().remove(0) };
+        let mut payload = encode_agent(&entry);
+        payload.truncate(38 + entry.genome.len() * 4);
+
+        match decode_binary(TAG_AGENT, &payload, Position::Record(2)).unwrap() {
+            Record::Agent(decoded) => assert_eq!(json(&decoded), json(&entry)),
+            _ => panic!("expected an agent entry"),
+        }
+    }
+}
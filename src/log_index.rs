@@ -0,0 +1,176 @@
+//! Sidecar index of a log, mapping generation numbers to where they start.
+//!
+//! The index lives next to the log as `<log>.idx`, with one line per
+//! generation: `<generation> <byte offset> <record number>`. `History`
+//! appends to it as it goes, and `convert` writes one for its output. Logs
+//! without one (older ones) are indexed by scanning them, which is saved
+//! when reviving from them but not by read-only commands.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::ops::RangeInclusive;
+
+use log::{debug, warn};
+
+use super::log_format::Record;
+use super::log_reader::{Generation, LogError, LogReader};
+
+#[derive(Debug, Clone)]
+pub struct IndexEntry {
+    pub generation: usize,
+    /// Byte offset of the generation marker.
+    pub offset: u64,
+    /// Record (or line) number of the generation marker, counting from 1.
+    pub record: usize,
+}
+
+impl IndexEntry {
+    pub fn write(&self, write: &mut impl Write) -> io::Result<()> {
+        writeln!(write, "{} {} {}", self.generation, self.offset, self.record)
+    }
+
+    fn parse(line: &str) -> Option<IndexEntry> {
+        let mut fields = line.split(' ').map(|field| field.parse::<u64>().ok());
+        let entry = IndexEntry {
+            generation: fields.next()?? as usize,
+            offset: fields.next()??,
+            record: fields.next()?? as usize,
+        };
+        match fields.next() {
+            None => Some(entry),
+            Some(_) => None,
+        }
+    }
+}
+
+pub struct LogIndex {
+    log: String,
+    entries: Vec<IndexEntry>,
+}
+
+pub fn index_path(log: &str) -> String {
+    format!("{}.idx", log)
+}
+
+impl LogIndex {
+    /// Load the sidecar index of `log`, or build and save it if there is
+    /// none.
+    pub fn open(log: &str) -> Result<LogIndex, LogError> {
+        match LogIndex::load(log) {
+            Ok(index) => Ok(index),
+            Err(error) => {
+                debug!("no usable index for {} ({}), scanning", log, error);
+                let index = LogIndex::build(log)?;
+                if let Err(error) = index.save() {
+                    warn!("couldn't save index of {}: {}", log, error);
+                }
+                Ok(index)
+            }
+        }
+    }
+
+    /// Load the sidecar index of `log`, or build it without saving it.
+    pub fn load_or_build(log: &str) -> Result<LogIndex, LogError> {
+        LogIndex::load(log).or_else(|error| {
+            debug!("no usable index for {} ({}), scanning", log, error);
+            LogIndex::build(log)
+        })
+    }
+
+    /// Index `log` from scratch, replacing any sidecar index.
+    pub fn rebuild(log: &str) -> Result<LogIndex, LogError> {
+        let index = LogIndex::build(log)?;
+        index.save()?;
+        Ok(index)
+    }
+
+    fn load(log: &str) -> io::Result<LogIndex> {
+        let file = File::open(index_path(log))?;
+        let mut entries = vec![];
+        for line in BufReader::new(file).lines() {
+            // A malformed line can only be a partially written last one.
+            match IndexEntry::parse(&line?) {
+                Some(entry) => entries.push(entry),
+                None => break,
+            }
+        }
+
+        Ok(LogIndex {
+            log: log.to_string(),
+            entries,
+        })
+    }
+
+    fn build(log: &str) -> Result<LogIndex, LogError> {
+        let mut reader = LogReader::open(log)?;
+        let mut entries = vec![];
+        while let Some(record) = reader.next() {
+            if let Ok(Record::Generation(generation, _)) = record {
+                let (offset, record) = reader.last_record();
+                entries.push(IndexEntry { generation, offset, record });
+            }
+        }
+
+        Ok(LogIndex {
+            log: log.to_string(),
+            entries,
+        })
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let mut write = io::BufWriter::new(File::create(index_path(&self.log))?);
+        for entry in &self.entries {
+            entry.write(&mut write)?;
+        }
+        write.flush()
+    }
+
+    /// Numbers of all generations that have been started in the log.
+    pub fn generations(&self) -> impl Iterator<Item = usize> + '_ {
+        self.entries.iter().map(|entry| entry.generation)
+    }
+
+    /// Open the log at generation `first`, or at the first generation after
+    /// it if it isn't in the log.
+    pub fn reader_at(&self, first: usize) -> Result<LogReader<BufReader<File>>, LogError> {
+        let mut reader = LogReader::open(&self.log)?;
+        if let Some(entry) = self.entries.iter().find(|entry| entry.generation >= first) {
+            reader.seek(entry)?;
+        }
+        Ok(reader)
+    }
+
+    /// Load the generations in `range`.
+    pub fn range(&self, range: RangeInclusive<usize>) -> Result<impl Iterator<Item = Generation>, LogError> {
+        let end = *range.end();
+        Ok(self.reader_at(*range.start())?
+            .generations()
+            .skip_while(move |generation| !range.contains(&generation.number))
+            .take_while(move |generation| generation.number <= end))
+    }
+}
+
+/// Print the generations in a log, with a summary of each if a range is
+/// given.
+pub fn list(log: &str, from: Option<usize>, to: Option<usize>) -> Result<(), LogError> {
+    let index = LogIndex::load_or_build(log)?;
+    if from.is_none() && to.is_none() {
+        for generation in index.generations() {
+            println!("{}", generation);
+        }
+        return Ok(());
+    }
+
+    for generation in index.range(from.unwrap_or(0)..=to.unwrap_or(usize::MAX))? {
+        let survivors = generation.agents.iter().filter(|agent| agent.survived).count();
+        println!(
+            "{}: {} of {} agents, {} survived{}",
+            generation.number,
+            generation.agents.len(),
+            generation.settings.num_agents,
+            survivors,
+            if generation.is_complete() { "" } else { " (incomplete)" },
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,405 @@
+//! Reading back logs written by `History`.
+//!
+//! A log is a header followed by generation markers, each followed by the
+//! agent entries of that generation; see `log_format` for the encodings.
+//! Runs may be killed at any point, so the reader has to cope with a
+//! partially written last record or generation.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+
+use log::{debug, warn};
+use uuid::Uuid;
+
+use super::history::{AgentEntry, Header, SCHEMA_VERSION};
+use super::log_index::IndexEntry;
+use super::log_format::{LogFormat, Record, MAGIC, BINARY_VERSION, MAX_RECORD_LENGTH, TAG_AGENT, decode_binary, decode_binary_header, decode_json, is_known_kind, is_known_tag};
+use super::stats::GenerationStats;
+use super::trajectory::ZonePlacement;
+use super::Settings;
+
+/// Where in a log something is: a line of a JSON log, or a record of a
+/// binary one. Both count from 1, including the header.
+#[derive(Debug, Clone, Copy)]
+pub enum Position {
+    Line(usize),
+    Record(usize),
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Position::Line(line) => write!(f, "line {}", line),
+            Position::Record(record) => write!(f, "record {}", record),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LogError {
+    Io(std::io::Error),
+    /// The log doesn't even have a header.
+    Empty,
+    /// A binary log of a format version this build can't read.
+    UnsupportedVersion(u16),
+    /// A log written with a newer schema than this build knows.
+    UnsupportedSchema(u32),
+    Header { at: Position, error: serde_json::Error },
+    /// A record doesn't have the expected structure.
+    Malformed { at: Position, reason: String },
+    Settings { at: Position, error: serde_json::Error },
+    Agent { at: Position, error: serde_json::Error },
+    /// The last record was cut off, e.g. because the run was killed.
+    Truncated { at: Position },
+    /// A record appears before the first generation marker.
+    Orphan { at: Position },
+    /// There is no complete generation (at or before the requested one).
+    NoGeneration(Option<usize>),
+    /// None of the selected agents are in the log.
+    NoAgents,
+    /// Genomes from the log mean something else under the settings they
+    /// would be used with; names the setting that differs.
+    Incompatible(&'static str),
+    /// A queried agent isn't in the log.
+    UnknownAgent(Uuid),
+}
+
+impl fmt::Display for LogError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LogError::Io(error) => write!(f, "{}", error),
+            LogError::Empty => write!(f, "log is empty"),
+            LogError::UnsupportedVersion(version) => write!(f, "unsupported binary log version {}", version),
+            LogError::UnsupportedSchema(schema) => write!(f, "unsupported log schema {}", schema),
+            LogError::Header { at, error } => write!(f, "{}: invalid header: {}", at, error),
+            LogError::Malformed { at, reason } => write!(f, "{}: malformed record: {}", at, reason),
+            LogError::Settings { at, error } => write!(f, "{}: invalid settings: {}", at, error),
+            LogError::Agent { at, error } => write!(f, "{}: invalid agent entry: {}", at, error),
+            LogError::Truncated { at } => write!(f, "{}: truncated", at),
+            LogError::Orphan { at } => write!(f, "{}: record outside of any generation", at),
+            LogError::NoGeneration(None) => write!(f, "no complete generation in log"),
+            LogError::NoGeneration(Some(generation)) => write!(f, "no complete generation at or before {}", generation),
+            LogError::NoAgents => write!(f, "no agents match the selection"),
+            LogError::Incompatible(setting) => write!(f, "{} differs from the first source", setting),
+            LogError::UnknownAgent(id) => write!(f, "agent {} not in log", id),
+        }
+    }
+}
+
+impl std::error::Error for LogError {}
+
+impl From<std::io::Error> for LogError {
+    fn from(error: std::io::Error) -> LogError {
+        LogError::Io(error)
+    }
+}
+
+/// Iterates over the records of a log of either format, returning an error
+/// for every record that can't be read. Reading continues after an error.
+pub struct LogReader<R> {
+    read: R,
+    format: LogFormat,
+    header: Header,
+    /// Number of records (or lines) read, including the header.
+    count: usize,
+    /// Bytes read so far.
+    offset: u64,
+    /// Where the last record read starts.
+    record_offset: u64,
+    buffer: Vec<u8>,
+    failed: bool,
+    skip_agents: bool,
+}
+
+impl LogReader<BufReader<File>> {
+    pub fn open(path: &str) -> Result<LogReader<BufReader<File>>, LogError> {
+        LogReader::new(BufReader::new(File::open(path)?))
+    }
+
+    /// Continue reading at an indexed generation.
+    pub fn seek(&mut self, entry: &IndexEntry) -> std::io::Result<()> {
+        self.read.seek(SeekFrom::Start(entry.offset))?;
+        self.offset = entry.offset;
+        self.count = entry.record - 1;
+        self.failed = false;
+        Ok(())
+    }
+}
+
+/// Like `read_exact`, but returns how much was read if the input ends early.
+fn read_full(read: &mut impl Read, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match read.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(error) if error.kind() == std::io::ErrorKind::Interrupted => (),
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(filled)
+}
+
+impl<R: BufRead> LogReader<R> {
+    pub fn new(mut read: R) -> Result<LogReader<R>, LogError> {
+        let format = if read.fill_buf()?.starts_with(MAGIC) {
+            LogFormat::Binary
+        }
+        else {
+            LogFormat::Json
+        };
+
+        let mut reader = LogReader {
+            read,
+            format,
+            header: Header::new(),
+            count: 0,
+            offset: 0,
+            record_offset: 0,
+            buffer: vec![],
+            failed: false,
+            skip_agents: false,
+        };
+
+        if format == LogFormat::Binary {
+            let mut preamble = [0; MAGIC.len() + 2];
+            reader.read.read_exact(&mut preamble)?;
+            reader.offset = preamble.len() as u64;
+            let version = u16::from_le_bytes([preamble[MAGIC.len()], preamble[MAGIC.len() + 1]]);
+            if version != BINARY_VERSION {
+                return Err(LogError::UnsupportedVersion(version));
+            }
+        }
+
+        reader.header = match reader.read_raw()? {
+            None => return Err(LogError::Empty),
+            Some(tag) => {
+                let at = reader.location();
+                match format {
+                    LogFormat::Json => serde_json::from_slice(&reader.buffer)
+                        .map_err(|error| LogError::Header { at, error })?,
+                    LogFormat::Binary => decode_binary_header(tag, &reader.buffer, at)?,
+                }
+            }
+        };
+        if reader.header.schema > SCHEMA_VERSION {
+            return Err(LogError::UnsupportedSchema(reader.header.schema));
+        }
+
+        Ok(reader)
+    }
+
+    /// Skip agent entries without decoding them, for when only the
+    /// generations and their statistics are of interest.
+    pub fn without_agents(mut self) -> LogReader<R> {
+        self.skip_agents = true;
+        self
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn format(&self) -> LogFormat {
+        self.format
+    }
+
+    /// Byte offset and number of the record read last.
+    pub fn last_record(&self) -> (u64, usize) {
+        (self.record_offset, self.count)
+    }
+
+    fn location(&self) -> Position {
+        match self.format {
+            LogFormat::Json => Position::Line(self.count),
+            LogFormat::Binary => Position::Record(self.count),
+        }
+    }
+
+    /// Group the records into generations. Corrupt records are skipped with
+    /// a warning.
+    pub fn generations(self) -> Generations<R> {
+        Generations {
+            records: self,
+            next: None,
+        }
+    }
+
+    fn is_agent(&self, tag: u8) -> bool {
+        match self.format {
+            LogFormat::Json => self.buffer.first() == Some(&b'{'),
+            LogFormat::Binary => tag == TAG_AGENT,
+        }
+    }
+
+    /// Whether the record in `buffer` is of a type this build knows.
+    fn is_known(&self, tag: u8) -> bool {
+        match self.format {
+            LogFormat::Json => match self.buffer.strip_prefix(b"#") {
+                Some(named) => {
+                    let kind = named.split(|byte| *byte == b' ').next().unwrap_or_default();
+                    std::str::from_utf8(kind).is_ok_and(is_known_kind)
+                }
+                None => true,
+            },
+            LogFormat::Binary => is_known_tag(tag),
+        }
+    }
+
+    /// Read the next record into `buffer` without decoding it. For binary
+    /// logs, returns the record type.
+    fn read_raw(&mut self) -> Result<Option<u8>, LogError> {
+        self.buffer.clear();
+        self.record_offset = self.offset;
+        match self.format {
+            LogFormat::Json => {
+                let length = self.read.read_until(b'\n', &mut self.buffer)?;
+                if length == 0 {
+                    return Ok(None);
+                }
+                self.count += 1;
+                self.offset += length as u64;
+                if self.buffer.pop() != Some(b'\n') {
+                    return Err(LogError::Truncated { at: self.location() });
+                }
+                if self.buffer.last() == Some(&b'\r') {
+                    self.buffer.pop();
+                }
+                Ok(Some(0))
+            }
+            LogFormat::Binary => {
+                let mut prefix = [0; 5];
+                let read = read_full(&mut self.read, &mut prefix)?;
+                self.offset += read as u64;
+                match read {
+                    0 => return Ok(None),
+                    5 => (),
+                    _ => {
+                        self.count += 1;
+                        return Err(LogError::Truncated { at: self.location() });
+                    }
+                }
+                self.count += 1;
+
+                let length = u32::from_le_bytes([prefix[1], prefix[2], prefix[3], prefix[4]]) as usize;
+                if length > MAX_RECORD_LENGTH {
+                    // Where the next record starts is lost along with this one.
+                    self.failed = true;
+                    return Err(LogError::Malformed { at: self.location(), reason: format!("record length {}", length) });
+                }
+                self.buffer.resize(length, 0);
+                let read = read_full(&mut self.read, &mut self.buffer)?;
+                self.offset += read as u64;
+                if read < length {
+                    return Err(LogError::Truncated { at: self.location() });
+                }
+                Ok(Some(prefix[0]))
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for LogReader<R> {
+    type Item = Result<Record, LogError>;
+
+    fn next(&mut self) -> Option<Result<Record, LogError>> {
+        if self.failed {
+            return None;
+        }
+
+        let tag = loop {
+            match self.read_raw() {
+                Ok(None) => return None,
+                Ok(Some(tag)) if self.skip_agents && self.is_agent(tag) => continue,
+                Ok(Some(tag)) if !self.is_known(tag) => {
+                    debug!("skipping unknown record type: {}", self.location());
+                    continue;
+                }
+                Ok(Some(tag)) => break tag,
+                Err(LogError::Io(error)) => {
+                    // Don't keep retrying a broken reader.
+                    self.failed = true;
+                    return Some(Err(LogError::Io(error)));
+                }
+                Err(error) => return Some(Err(error)),
+            }
+        };
+
+        let at = self.location();
+        Some(match self.format {
+            LogFormat::Json => match std::str::from_utf8(&self.buffer) {
+                Ok(line) => decode_json(line, at),
+                Err(error) => Err(LogError::Malformed { at, reason: error.to_string() }),
+            },
+            LogFormat::Binary => decode_binary(tag, &self.buffer, at),
+        })
+    }
+}
+
+pub struct Generation {
+    pub number: usize,
+    pub settings: Settings,
+    /// Zones the agents were judged against, in the order they were placed.
+    pub zones: Vec<ZonePlacement>,
+    pub agents: Vec<AgentEntry>,
+    pub stats: Option<GenerationStats>,
+}
+
+impl Generation {
+    pub fn is_complete(&self) -> bool {
+        self.agents.len() == self.settings.num_agents
+    }
+}
+
+pub struct Generations<R> {
+    records: LogReader<R>,
+    next: Option<(usize, Settings)>,
+}
+
+impl<R: BufRead> Iterator for Generations<R> {
+    type Item = Generation;
+
+    fn next(&mut self) -> Option<Generation> {
+        let mut current = self.next.take().map(|(number, settings)| Generation {
+            number,
+            settings,
+            zones: vec![],
+            agents: vec![],
+            stats: None,
+        });
+
+        while let Some(record) = self.records.next() {
+            match record {
+                Ok(Record::Generation(number, settings)) => {
+                    if current.is_some() {
+                        self.next = Some((number, settings));
+                        break;
+                    }
+                    current = Some(Generation {
+                        number,
+                        settings,
+                        zones: vec![],
+                        agents: vec![],
+                        stats: None,
+                    });
+                }
+                Ok(Record::Zone(placement)) => match &mut current {
+                    Some(generation) => generation.zones.push(placement),
+                    None => warn!("skipping record: {}", LogError::Orphan { at: self.records.location() }),
+                },
+                Ok(Record::Agent(entry)) => match &mut current {
+                    Some(generation) => generation.agents.push(entry),
+                    None => warn!("skipping record: {}", LogError::Orphan { at: self.records.location() }),
+                },
+                Ok(Record::Stats(stats)) => match &mut current {
+                    Some(generation) => generation.stats = Some(stats),
+                    None => warn!("skipping record: {}", LogError::Orphan { at: self.records.location() }),
+                },
+                Err(error) => warn!("skipping record: {}", error),
+            }
+        }
+
+        current
+    }
+}
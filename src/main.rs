@@ -1,16 +1,31 @@
 use rand::{Rng, prelude::SliceRandom};
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
-use log::{info};
+use log::{info, warn, error, debug};
 use clap::Parser;
 
 mod dot;
 mod viewer;
 mod brain;
+mod checkpoint;
+mod ctrnn;
 mod genetics;
 mod history;
-
-use brain::Brain;
+mod lineage;
+mod log_format;
+mod log_index;
+mod log_reader;
+mod physics;
+mod random;
+mod replay;
+mod revive;
+mod runs;
+mod sensors;
+mod sink;
+mod stats;
+mod trajectory;
+
+use brain::{Brain, BrainType};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum SimulationMode {
@@ -28,10 +43,21 @@ pub struct Settings {
     pub mutation_rate: f32,
     pub mutation_strength: f32,
     pub num_agents: usize,
+    /// Seconds per step. `Movement::Direct` moves a fixed amount per step,
+    /// so changing this also changes how fast direct agents move.
     pub time_step: f32,
+    /// Milliseconds between frames sent to viewer clients.
     pub frame_interval: u32,
     pub mode: SimulationMode,
     pub generation_time: f32,
+    #[serde(default)]
+    pub brain: BrainType,
+    #[serde(default)]
+    pub sensors: sensors::Sensors,
+    #[serde(default)]
+    pub noise: sensors::Noise,
+    #[serde(default)]
+    pub movement: physics::Movement,
 }
 
 #[derive(Parser)]
@@ -56,21 +82,178 @@ pub struct Args {
     #[clap(long)]
     generation_time: Option<f32>,
 
-    /// Seed from log file.
+    /// Override brain type.
+    #[clap(long, arg_enum)]
+    brain: Option<BrainType>,
+
+    /// Override which inputs agents are given.
+    #[clap(long, arg_enum)]
+    sensors: Option<sensors::SensorKind>,
+
+    /// Override oscillator frequencies (Hz) of normalized sensors.
+    #[clap(long, number_of_values = 2)]
+    oscillator_frequencies: Option<Vec<f32>>,
+
+    /// Override standard deviation of noise added to inputs.
+    #[clap(long)]
+    input_noise: Option<f32>,
+
+    /// Override probability that an input drops out on a step.
+    #[clap(long)]
+    input_dropout: Option<f32>,
+
+    /// Override standard deviation of noise added to speed outputs.
+    #[clap(long)]
+    output_noise: Option<f32>,
+
+    /// Override number of steps by which inputs are delayed.
+    #[clap(long)]
+    sensor_delay: Option<usize>,
+
+    /// Override how brain outputs move agents.
+    #[clap(long, arg_enum)]
+    movement: Option<physics::MovementKind>,
+
+    /// Seed the random number generator, for reproducible runs.
+    #[clap(long)]
+    seed: Option<u64>,
+
+    /// Seed from log file: `<log>[,generation=<n>][,survivors][,agents=<id>+...][,share=<weight>]`.
+    /// Repeat to mix several sources into one founding population.
     #[clap(long)]
-    revive: Option<String>,
+    revive: Vec<revive::Source>,
 
-    /// If seeding from log file, use this generation.
+    /// If seeding from log file, use this generation unless the source
+    /// names one.
     #[clap(long, requires = "revive")]
     generation: Option<usize>,
     
+    /// Continue a run exactly where a checkpoint left it. Settings can't be
+    /// overridden.
+    #[clap(long, conflicts_with = "revive")]
+    resume: Option<String>,
+
+    /// Write a checkpoint next to the log this often (seconds).
+    #[clap(long)]
+    checkpoint_interval: Option<f32>,
+
     /// Enable viewer
     #[clap(long)]
     viewer: bool,
 
-    /// Disable logging (not implemented)
+    /// Disable logging; same as `--log none`.
     #[clap(long)]
     no_log: bool,
+
+    /// Where to write the log: a directory, `-` for stdout, `tcp:<address>`,
+    /// `unix:<path>` or `none`.
+    #[clap(long, default_value = "output")]
+    log: sink::Sink,
+
+    /// Where to stream per-generation statistics as CSV, like `--log`, but
+    /// not to the same stdout.
+    #[clap(long, default_value = "none")]
+    stats: sink::Sink,
+
+    /// Format of the log.
+    #[clap(long, arg_enum, default_value = "json")]
+    log_format: log_format::LogFormat,
+
+    /// Stop after this generation.
+    #[clap(long)]
+    max_generations: Option<usize>,
+
+    /// Record trajectories of every Kth generation.
+    #[clap(long)]
+    trajectory_every: Option<usize>,
+
+    /// Record trajectories of the last generation.
+    #[clap(long, requires = "max-generations")]
+    trajectory_last: bool,
+
+    /// Steps between recorded trajectory positions.
+    #[clap(long, default_value = "10")]
+    trajectory_interval: usize,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(clap::Subcommand)]
+pub enum Command {
+    /// Convert a log to another format.
+    Convert {
+        input: String,
+        output: String,
+
+        #[clap(long, arg_enum)]
+        format: log_format::LogFormat,
+    },
+
+    /// List the generations in a log.
+    Generations {
+        log: String,
+
+        /// Summarize generations from this one on.
+        #[clap(long)]
+        from: Option<usize>,
+
+        /// Summarize generations up to this one.
+        #[clap(long)]
+        to: Option<usize>,
+    },
+
+    /// Print the per-generation statistics of a log as CSV.
+    Stats {
+        log: String,
+    },
+
+    /// Analyze the ancestry of the agents in a log.
+    Lineage {
+        log: String,
+
+        #[clap(subcommand)]
+        query: lineage::Query,
+    },
+
+    /// Show which runs were revived or reseeded from which.
+    Runs {
+        /// Directory containing the logs.
+        #[clap(long, default_value = "output")]
+        dir: String,
+
+        /// Print a Graphviz graph instead of a tree.
+        #[clap(long)]
+        dot: bool,
+    },
+
+    /// Replay a logged generation in the viewer, without evolving or logging.
+    Replay {
+        log: String,
+
+        generation: usize,
+
+        /// Play every generation up to this one once, instead of repeating
+        /// a single generation.
+        #[clap(long)]
+        to: Option<usize>,
+    },
+}
+
+fn run_command(command: Command) {
+    let result = match command {
+        Command::Convert { input, output, format } => log_format::convert(&input, &output, format),
+        Command::Generations { log, from, to } => log_index::list(&log, from, to),
+        Command::Stats { log } => stats::print_csv(&log),
+        Command::Lineage { log, query } => lineage::query(&log, query),
+        Command::Runs { dir, dot } => runs::print(&dir, dot),
+        Command::Replay { log, generation, to } => replay::replay(&log, generation, to),
+    };
+
+    if let Err(e) = result {
+        error!("{}", e);
+        std::process::exit(1);
+    }
 }
 
 pub struct Agent {
@@ -78,7 +261,11 @@ pub struct Agent {
     parent: Option<Uuid>,
     position: (f32, f32),
     genome: genetics::Genome,
-    brain: brain::Brain,
+    brain: Box<dyn brain::Controller>,
+    sensor_delay: sensors::DelayLine,
+    motion: physics::Motion,
+    /// Accumulated over the current generation.
+    outcome: history::Outcome,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -90,7 +277,7 @@ pub struct Zone {
 
 impl Zone {
     fn random(world_radius: f32, radius: std::ops::Range<f32>) -> Zone {
-        let mut rng = rand::thread_rng();
+        let mut rng = random::rng();
         let r = (world_radius - radius.end) * rng.gen::<f32>().sqrt();
         let theta = rng.gen::<f32>() * 2.0 * std::f32::consts::PI;
         let radius = rng.gen_range(radius);
@@ -104,6 +291,11 @@ impl Zone {
     fn contains(&self, position: (f32, f32)) -> bool {
         (position.0 - self.x).powf(2.0) + (position.1 - self.y).powf(2.0) < self.radius.powf(2.0)
     }
+
+    /// Distance from `position` to the center.
+    fn distance(&self, position: (f32, f32)) -> f32 {
+        ((position.0 - self.x).powf(2.0) + (position.1 - self.y).powf(2.0)).sqrt()
+    }
 }
 
 fn keep_inside_radius(mut position: (f32, f32), radius: f32) -> (f32, f32) {
@@ -117,15 +309,18 @@ fn keep_inside_radius(mut position: (f32, f32), radius: f32) -> (f32, f32) {
 }
 
 impl Agent {
-    fn new() -> Agent {
+    fn new(brain_type: BrainType) -> Agent {
         let genome = genetics::randomize();
-        let brain = genetics::create_brain(&genome);
-        let mut rng = rand::thread_rng();
+        let brain = genetics::create_controller(&genome, brain_type);
+        let mut rng = random::rng();
         Agent {
             genome,
             brain,
-            uuid: Uuid::new_v4(),
+            uuid: random::uuid(),
             parent: None,
+            sensor_delay: sensors::DelayLine::new(),
+            motion: physics::Motion::random(),
+            outcome: history::Outcome::default(),
             position: (
                 (rng.gen::<f32>() * 2.0 - 1.0) * 300.0,
                 (rng.gen::<f32>() * 2.0 - 1.0) * 300.0,
@@ -133,30 +328,40 @@ impl Agent {
         }
     }
 
-    fn simulate(&mut self, time: f32, world_radius: f32, safe_zone: &Zone) {
-        self.brain.input(brain::Input::Constant, 1.0);
-        self.brain.input(brain::Input::Oscillator, time * std::f32::consts::TAU);
-        self.brain.input(brain::Input::X, self.position.0);
-        self.brain.input(brain::Input::Y, self.position.1);
-        self.brain.input(brain::Input::SafeX, safe_zone.x);
-        self.brain.input(brain::Input::SafeY, safe_zone.y);
-        self.brain.input(brain::Input::SafeRadius, safe_zone.radius);
-        self.brain.simulate();
-        self.position.0 += self.brain.output(brain::Output::SpeedX);
-        self.position.1 += self.brain.output(brain::Output::SpeedY);
-        self.position = keep_inside_radius(self.position, world_radius);
+    fn simulate(&mut self, time: f32, settings: &Settings, safe_zone: &Zone) {
+        let inputs = settings.sensors.read(self.position, time, settings.world_radius, safe_zone);
+        let mut inputs = self.sensor_delay.delay(inputs, settings.noise.sensor_delay);
+        settings.noise.apply_to_inputs(&mut inputs, &settings.sensors);
+        sensors::feed(self.brain.as_mut(), &inputs);
+        self.brain.simulate(settings.time_step);
+        let outputs = (
+            settings.noise.apply_to_output(self.brain.output(brain::Output::SpeedX)),
+            settings.noise.apply_to_output(self.brain.output(brain::Output::SpeedY)),
+        );
+        let last_position = self.position;
+        settings.movement.apply(&mut self.position, &mut self.motion, outputs, settings.time_step);
+        self.position = keep_inside_radius(self.position, settings.world_radius);
+
+        self.outcome.path_length += ((self.position.0 - last_position.0).powf(2.0) + (self.position.1 - last_position.1).powf(2.0)).sqrt();
+        if safe_zone.contains(self.position) {
+            self.outcome.first_entry.get_or_insert(time);
+            self.outcome.time_in_zone += settings.time_step;
+        }
     }
 
-    fn procreate(&self, rate: f32, strength: f32) -> Agent {
-        let mut rng = rand::thread_rng();
+    fn procreate(&self, rate: f32, strength: f32, brain_type: BrainType) -> Agent {
+        let mut rng = random::rng();
         let mut genome = self.genome.clone();
         genetics::mutate(&mut genome, rate, strength);
-        let brain = genetics::create_brain(&genome);
+        let brain = genetics::create_controller(&genome, brain_type);
         Agent {
             genome,
             brain,
-            uuid: Uuid::new_v4(),
+            uuid: random::uuid(),
             parent: Some(self.uuid),
+            sensor_delay: sensors::DelayLine::new(),
+            motion: physics::Motion::random(),
+            outcome: history::Outcome::default(),
             position: (
                 (rng.gen::<f32>() * 2.0 - 1.0) * 300.0,
                 (rng.gen::<f32>() * 2.0 - 1.0) * 300.0,
@@ -164,13 +369,16 @@ impl Agent {
         }
     }
 
-    fn clone(&self) -> Agent {
-        let mut rng = rand::thread_rng();
+    fn clone(&self, brain_type: BrainType) -> Agent {
+        let mut rng = random::rng();
         Agent {
-            brain: genetics::create_brain(&self.genome),
+            brain: genetics::create_controller(&self.genome, brain_type),
             genome: self.genome,
-            uuid: Uuid::new_v4(),
+            uuid: random::uuid(),
             parent: None,
+            sensor_delay: sensors::DelayLine::new(),
+            motion: physics::Motion::random(),
+            outcome: history::Outcome::default(),
             position: (
                 (rng.gen::<f32>() * 2.0 - 1.0) * 300.0,
                 (rng.gen::<f32>() * 2.0 - 1.0) * 300.0,
@@ -185,27 +393,177 @@ fn override_setting<T: Clone>(setting: &mut T, option: &Option<T>) {
     }
 }
 
+/// Override settings with command line arguments.
+fn override_settings(settings: &mut Settings, args: &Args) {
+    override_setting(&mut settings.mutation_rate, &args.mutation_rate);
+    override_setting(&mut settings.mutation_strength, &args.mutation_strength);
+    override_setting(&mut settings.generation_time, &args.generation_time);
+    override_setting(&mut settings.brain, &args.brain);
+    let frequencies = match (&args.oscillator_frequencies, &settings.sensors) {
+        (Some(frequencies), _) => [frequencies[0], frequencies[1]],
+        (None, sensors::Sensors::Normalized { oscillator_frequencies }) => *oscillator_frequencies,
+        (None, _) => sensors::DEFAULT_OSCILLATOR_FREQUENCIES,
+    };
+    let kind = match (args.sensors, &settings.sensors) {
+        (Some(kind), _) => kind,
+        (None, sensors::Sensors::Raw) => sensors::SensorKind::Raw,
+        (None, sensors::Sensors::Normalized { .. }) => sensors::SensorKind::Normalized,
+    };
+    settings.sensors = sensors::Sensors::new(kind, frequencies);
+    override_setting(&mut settings.noise.input_stddev, &args.input_noise);
+    override_setting(&mut settings.noise.input_dropout, &args.input_dropout);
+    override_setting(&mut settings.noise.output_stddev, &args.output_noise);
+    override_setting(&mut settings.noise.sensor_delay, &args.sensor_delay);
+    match (args.movement, &settings.movement) {
+        // Keep the parameters of a revived physical model.
+        (Some(physics::MovementKind::Physical), physics::Movement::Physical { .. }) => (),
+        (Some(kind), _) => settings.movement = physics::Movement::new(kind),
+        (None, _) => (),
+    }
+    match &mut settings.mode {
+        SimulationMode::SafeZoneRace { radius_low, radius_high } => {
+            override_setting(radius_low, &args.safe_lower);
+            override_setting(radius_high, &args.safe_upper);
+        }
+    }
+}
+
+/// How far a generation has been simulated.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Progress {
+    time: f32,
+    steps: usize,
+    zone: Zone,
+    /// Every zone placed so far, starting with the first.
+    #[serde(default)]
+    zones: Vec<trajectory::ZonePlacement>,
+}
+
+impl Progress {
+    fn start(zone: Zone) -> Progress {
+        Progress {
+            time: 0.0,
+            steps: 0,
+            zones: vec![trajectory::ZonePlacement { time: 0.0, zone: zone.clone() }],
+            zone,
+        }
+    }
+}
+
+/// Called after every step, with the recorder so it can be checkpointed.
+type OnStep<'a> = dyn FnMut(&[Agent], &Settings, &Progress, Option<&trajectory::Recorder>) + 'a;
+
+/// Simulate a generation from `start` to the end, calling `place_zone` with
+/// the current time to re-place the safe zone halfway through, and `on_step`
+/// after every step. Returns the progress at the end, with the final zone.
+fn simulate_generation(
+    agents: &mut [Agent],
+    settings: &mut Settings,
+    place_zone: &mut dyn FnMut(f32) -> Zone,
+    viewer: &viewer::ViewerHandle,
+    mut recorder: Option<&mut trajectory::Recorder>,
+    start: Progress,
+    on_step: &mut OnStep<'_>,
+) -> Progress {
+    let mut progress = start;
+    settings.zone = Some(progress.zone.clone());
+
+    viewer.publish(viewer::Event::Settings(settings.clone()));
+    viewer.publish(viewer::Event::Clear);
+    viewer.spawn(agents);
+
+    // Set when a client skips the rest of the generation.
+    let mut skipping = false;
+    while progress.time < settings.generation_time {
+        if !skipping {
+            skipping = viewer.pace(settings.time_step, agents, &progress) == viewer::Control::SkipGeneration;
+        }
+
+        let last_time = progress.time;
+        progress.time += settings.time_step;
+
+        if last_time <= settings.generation_time / 2.0 && progress.time > settings.generation_time / 2.0 {
+            // Re-place safe zone
+            progress.zone = place_zone(last_time);
+            progress.zones.push(trajectory::ZonePlacement { time: last_time, zone: progress.zone.clone() });
+            settings.zone = Some(progress.zone.clone());
+            if let Some(recorder) = &mut recorder {
+                recorder.zone(last_time, &progress.zone);
+            }
+            viewer.publish(viewer::Event::Settings(settings.clone()));
+        }
+
+        if !skipping {
+            viewer.sample(agents, progress.time);
+        }
+
+        for agent in agents.iter_mut() {
+            agent.simulate(progress.time, settings, &progress.zone);
+        }
+        progress.steps += 1;
+        if let Some(recorder) = &mut recorder {
+            recorder.step(progress.time, agents);
+        }
+        on_step(agents, settings, &progress, recorder.as_deref());
+    }
+    if let Some(recorder) = &mut recorder {
+        recorder.end(progress.time, agents);
+    }
+
+    progress
+}
+
 fn main() {
     use simplelog::{CombinedLogger, TermLogger, LevelFilter, Config, TerminalMode, ColorChoice};
 
+    // Diagnostics go to stderr, so that logs and command output can be piped.
     CombinedLogger::init(
         vec![
-            TermLogger::new(LevelFilter::Debug, Config::default(), TerminalMode::Mixed, ColorChoice::Auto),
+            TermLogger::new(LevelFilter::Debug, Config::default(), TerminalMode::Stderr, ColorChoice::Auto),
             //WriteLogger::new(LevelFilter::Info, Config::default(), File::create("run.log").unwrap()),
         ]
     ).unwrap();
 
-    let args = Args::parse();
+    let mut args = Args::parse();
+    if let Some(command) = args.command.take() {
+        return run_command(command);
+    }
+    if !args.no_log && args.log == sink::Sink::Stdout && args.stats == sink::Sink::Stdout {
+        // Each stream is buffered on its own, so they would interleave
+        // mid-record.
+        error!("--log and --stats can't both be stdout");
+        std::process::exit(1);
+    }
 
     let viewer = if args.viewer {
-        viewer::start_viewer()
+        viewer::start_viewer(viewer::Playback::default())
     }
     else {
         viewer::ViewerHandle::Disabled
     };
 
-    let (start_agents, mut start_settings, start_header) = if let Some(filename) = args.revive {
-        history::History::revive(&filename, args.generation)
+    if let Some(seed) = args.seed {
+        random::seed(seed);
+    }
+
+    let checkpoint = args.resume.as_ref().map(|path| checkpoint::Checkpoint::load(path).unwrap_or_else(|e| {
+        error!("couldn't resume from {}: {}", path, e);
+        std::process::exit(1);
+    }));
+
+    let (start_agents, mut start_settings, start_header) = if let Some(checkpoint) = &checkpoint {
+        info!("resuming generation {} at {} seconds", checkpoint.generation, checkpoint.progress.time);
+        (
+            checkpoint.start_agents.iter().cloned().map(|entry| Agent::from_log_entry(entry, checkpoint.settings.brain)).collect(),
+            checkpoint.settings.clone(),
+            checkpoint.start_header.copy(),
+        )
+    }
+    else if !args.revive.is_empty() {
+        revive::revive(&args.revive, args.generation).unwrap_or_else(|e| {
+            error!("couldn't revive {}", e);
+            std::process::exit(1);
+        })
     }
     else {
         let mut agents = vec![];
@@ -216,14 +574,18 @@ fn main() {
             zone: None,
             mutation_rate: 0.03,
             mutation_strength: 0.25,
-            frame_interval: 5,
+            frame_interval: 33,
             time_step: 0.05,
             generation_time: 50.0,
             mode: SimulationMode::SafeZoneRace { radius_low: 50.0, radius_high: 100.0 },
+            brain: args.brain.unwrap_or_default(),
+            sensors: sensors::Sensors::default(),
+            noise: sensors::Noise::default(),
+            movement: physics::Movement::default(),
         };
 
         for _ in 0..settings.num_agents {
-            agents.push(Agent::new());
+            agents.push(Agent::new(settings.brain));
         }
 
         (
@@ -233,100 +595,166 @@ fn main() {
         )
     };
 
-    // Override start settings with args:
-    override_setting(&mut start_settings.mutation_rate, &args.mutation_rate);
-    override_setting(&mut start_settings.mutation_strength, &args.mutation_strength);
-    override_setting(&mut start_settings.generation_time, &args.generation_time);
-    match &mut start_settings.mode {
-        SimulationMode::SafeZoneRace { radius_low, radius_high } => {
-            override_setting(radius_low, &args.safe_lower);
-            override_setting(radius_high, &args.safe_upper);
-        }
+    // Override start settings with args, unless resuming:
+    if checkpoint.is_none() {
+        override_settings(&mut start_settings, &args);
     }
 
     let mut agents : Vec<Agent> = vec![];
     let mut settings = start_settings;
 
-    let mut log = history::History::new(start_header.clone());
+    let log_sink = if args.no_log { sink::Sink::None } else { args.log.clone() };
+    let log_format = checkpoint.as_ref().map_or(args.log_format, |checkpoint| checkpoint.format);
+    let open_log = |header| history::History::new(header, log_format, &log_sink, &args.stats).unwrap_or_else(|e| {
+        error!("couldn't open log: {}", e);
+        std::process::exit(1);
+    });
+    let output_directory = log_sink.directory().unwrap_or("output").to_string();
+    // Without a log directory, nothing else creates the fallback.
+    if args.checkpoint_interval.is_some() || args.trajectory_every.is_some() || args.trajectory_last {
+        std::fs::create_dir_all(&output_directory).unwrap_or_else(|e| {
+            error!("couldn't create {}: {}", output_directory, e);
+            std::process::exit(1);
+        });
+    }
+
+    let mut log = match &checkpoint {
+        Some(checkpoint) => history::History::resume(checkpoint.header.copy(), log_format, &log_sink, &args.stats, &checkpoint.log)
+            .unwrap_or_else(|e| {
+                error!("couldn't resume log: {}", e);
+                std::process::exit(1);
+            }),
+        None => open_log(start_header.clone()),
+    };
+    let trajectories = || trajectory::Selection {
+        every: args.trajectory_every,
+        last: args.max_generations.filter(|_| args.trajectory_last),
+        interval: args.trajectory_interval,
+    };
+    let mut recorder = trajectory::Recorder::new(log.id(), trajectories(), &output_directory);
 
-    let mut rng = rand::thread_rng();
+    let mut rng = random::rng();
     let mut generation = start_header.revived_generation.unwrap_or(1);
+    let mut resume = None;
+    let mut resumed_wall_time = std::time::Duration::ZERO;
+    if let Some(checkpoint) = checkpoint {
+        generation = checkpoint.generation;
+        agents = checkpoint.agents.into_iter().map(|state| Agent::from_checkpoint(state, settings.brain)).collect();
+        resumed_wall_time = std::time::Duration::from_secs_f32(checkpoint.wall_time);
+        resume = Some(checkpoint.progress);
+        if let Some(recording) = checkpoint.recording {
+            recorder.resume(recording);
+        }
+        // Last, since rebuilding the agents draws from it.
+        random::restore(checkpoint.rng);
+    }
+    let mut last_checkpoint = std::time::Instant::now();
+    let world_radius = settings.world_radius;
     match settings.mode {
         SimulationMode::SafeZoneRace { radius_low, radius_high } => {
             loop {
                 if agents.is_empty() {
                     info!("seeding...");
                     for agent in &start_agents {
-                        agents.push(agent.clone());
+                        agents.push(agent.clone(settings.brain));
                     }
                 }
 
-                info!("simulating generation {} for {} seconds", generation, settings.generation_time);
-                log.log_generation(generation, &settings);
+                // A resumed generation has already been logged.
+                if resume.is_none() {
+                    info!("simulating generation {} for {} seconds", generation, settings.generation_time);
+                    log.log_generation(generation, &settings);
+                }
 
 
                 settings.title = format!("Generation {}", generation);
 
-                // If we want to load safe zone from history:
-                //let safe_zone = match settings.zone {
-                //    Some(zone) => zone,
-                //    None => {
-                //        let safe_zone = Zone::random(settings.world_radius, radius_low..radius_high);
-                //        safe_zone
-                //    }
-                //};
-                let mut safe_zone = Zone::random(settings.world_radius, radius_low..radius_high);
-                settings.zone = Some(safe_zone.clone());
-
-                viewer.publish(viewer::Event::Settings(settings.clone()));
-                viewer.publish(viewer::Event::Clear);
-                viewer.publish(viewer::spawn(&agents));
-
-                let mut time: f32 = 0.0;
-                while time < settings.generation_time {
-                    if args.viewer {
-                        std::thread::sleep(std::time::Duration::from_millis(settings.frame_interval.into()));
-                    }
-
-                    let last_time = time;
-                    time += settings.time_step;
-
-                    if last_time <= settings.generation_time / 2.0 && time > settings.generation_time / 2.0 {
-                        // Re-place safe zone
-                        safe_zone = Zone::random(settings.world_radius, radius_low..radius_high);
-                        settings.zone = Some(safe_zone.clone());
-                        viewer.publish(viewer::Event::Settings(settings.clone()));
+                let started = std::time::Instant::now();
+                let mut save_checkpoint = |agents: &[Agent], settings: &Settings, progress: &Progress, recorder: Option<&trajectory::Recorder>| {
+                    if args.checkpoint_interval.is_none_or(|interval| last_checkpoint.elapsed().as_secs_f32() < interval) {
+                        return;
                     }
-
-                    viewer.publish(viewer::frame(&agents)); 
-
-                    for agent in &mut agents {
-                        agent.simulate(time, settings.world_radius, &safe_zone);
+                    last_checkpoint = std::time::Instant::now();
+
+                    let checkpoint = checkpoint::Checkpoint {
+                        log: log.position(),
+                        header: log.header().copy(),
+                        format: log_format,
+                        start_header: start_header.copy(),
+                        start_agents: start_agents.iter().map(|agent| agent.to_log_entry(false)).collect(),
+                        settings: settings.clone(),
+                        generation,
+                        progress: progress.clone(),
+                        wall_time: (resumed_wall_time + started.elapsed()).as_secs_f32(),
+                        agents: agents.iter().map(Agent::to_checkpoint).collect(),
+                        recording: recorder.and_then(trajectory::Recorder::recording),
+                        rng: random::state(),
+                    };
+                    let path = checkpoint::checkpoint_path(&output_directory, log.id());
+                    match checkpoint.save(&path) {
+                        Ok(()) => debug!("saved checkpoint {}", path.display()),
+                        Err(e) => warn!("couldn't save checkpoint {}: {}", path.display(), e),
                     }
+                };
+                let start = resume.take().unwrap_or_else(|| {
+                    let zone = Zone::random(world_radius, radius_low..radius_high);
+                    recorder.begin(generation, &agents, &zone);
+                    Progress::start(zone)
+                });
+                let progress = simulate_generation(
+                    &mut agents,
+                    &mut settings,
+                    &mut |_| Zone::random(world_radius, radius_low..radius_high),
+                    &viewer,
+                    Some(&mut recorder),
+                    start,
+                    &mut save_checkpoint,
+                );
+
+                let wall_time = resumed_wall_time + started.elapsed();
+                resumed_wall_time = std::time::Duration::ZERO;
+                let safe_zone = progress.zone;
+                let stats = stats::GenerationStats::compute(generation, &agents, &safe_zone, progress.steps, wall_time);
+
+                for placement in progress.zones {
+                    log.log_zone(placement);
                 }
 
+                viewer.publish(viewer::kill(&agents, &safe_zone));
+                viewer.publish(viewer::Event::Summary(stats.clone()));
+
                 // Impose selection!
                 let mut survivors = vec![];
                 for agent in agents {
-                    let survived = safe_zone.contains(agent.position);
-                    log.log_agent(agent.to_log_entry(survived));
+                    let entry = agent.judge(generation, &safe_zone);
+                    let survived = entry.survived;
+                    log.log_agent(entry);
                     if survived {
                         survivors.push(agent);
                     }
                 }
+                log.log_stats(stats);
+
+                if args.max_generations.is_some_and(|max| generation >= max) {
+                    info!("reached generation {}, stopping", generation);
+                    break;
+                }
 
                 agents = vec![];
                 if survivors.is_empty() {
                     info!("no survivors, reseeding");
                     generation = start_header.revived_generation.unwrap_or(1);
-                    log = history::History::new(start_header.clone());
+                    let mut header = start_header.clone();
+                    header.reseeded_from = Some(log.id());
+                    log = open_log(header);
+                    recorder = trajectory::Recorder::new(log.id(), trajectories(), &output_directory);
                 }
                 else {
                     info!("{} survivors", survivors.len());
 
                     // Randomly pick a survivor to procreate until we reach cap.
                     while agents.len() < settings.num_agents {
-                        agents.push(survivors.choose(&mut rng).unwrap().procreate(settings.mutation_rate, settings.mutation_strength));
+                        agents.push(survivors.choose(&mut rng).unwrap().procreate(settings.mutation_rate, settings.mutation_strength, settings.brain));
                     }
 
                     generation += 1;
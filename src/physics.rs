@@ -0,0 +1,109 @@
+//! How brain outputs move an agent.
+
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub enum Movement {
+    /// `SpeedX` and `SpeedY` are added to the position every step,
+    /// regardless of the time step. The original model.
+    #[default]
+    Direct,
+    /// `SpeedX` is thrust along the heading and `SpeedY` is the turning rate.
+    Physical {
+        /// Acceleration at full thrust, in units/s².
+        thrust: f32,
+        /// Turning rate at full output, in rad/s.
+        turn_rate: f32,
+        /// Fraction of velocity lost per second.
+        drag: f32,
+        /// Speed limit, in units/s.
+        max_speed: f32,
+        /// Energy spent per second at full thrust. Agents start every
+        /// generation with an energy of 1 and can't thrust once it's spent.
+        energy_cost: f32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ArgEnum)]
+pub enum MovementKind {
+    Direct,
+    Physical,
+}
+
+impl Movement {
+    pub fn new(kind: MovementKind) -> Movement {
+        match kind {
+            MovementKind::Direct => Movement::Direct,
+            MovementKind::Physical => Movement::Physical {
+                thrust: 100.0,
+                turn_rate: std::f32::consts::PI,
+                drag: 0.5,
+                max_speed: 50.0,
+                energy_cost: 0.05,
+            },
+        }
+    }
+
+    /// Move `position` according to the outputs `(SpeedX, SpeedY)`.
+    pub fn apply(&self, position: &mut (f32, f32), motion: &mut Motion, outputs: (f32, f32), time_step: f32) {
+        match self {
+            Movement::Direct => {
+                position.0 += outputs.0;
+                position.1 += outputs.1;
+
+                // Not used for movement, but reported to the viewer.
+                motion.velocity = (outputs.0 / time_step, outputs.1 / time_step);
+                if outputs != (0.0, 0.0) {
+                    motion.heading = outputs.1.atan2(outputs.0);
+                }
+            }
+            Movement::Physical { thrust, turn_rate, drag, max_speed, energy_cost } => {
+                let throttle = if motion.energy > 0.0 { outputs.0.clamp(-1.0, 1.0) } else { 0.0 };
+                motion.energy = (motion.energy - throttle.abs() * energy_cost * time_step).max(0.0);
+
+                motion.heading = (motion.heading + outputs.1.clamp(-1.0, 1.0) * turn_rate * time_step)
+                    .rem_euclid(std::f32::consts::TAU);
+
+                let acceleration = throttle * thrust * time_step;
+                let damping = (1.0 - drag).max(0.0).powf(time_step);
+                let mut velocity = (
+                    (motion.velocity.0 + motion.heading.cos() * acceleration) * damping,
+                    (motion.velocity.1 + motion.heading.sin() * acceleration) * damping,
+                );
+
+                let speed = (velocity.0.powf(2.0) + velocity.1.powf(2.0)).sqrt();
+                if speed > *max_speed {
+                    velocity.0 *= max_speed / speed;
+                    velocity.1 *= max_speed / speed;
+                }
+
+                motion.velocity = velocity;
+                position.0 += velocity.0 * time_step;
+                position.1 += velocity.1 * time_step;
+            }
+        }
+    }
+}
+
+/// Kinematic state of an agent besides its position.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Motion {
+    /// Direction the agent is facing, in radians.
+    pub heading: f32,
+    /// Velocity in units/s.
+    pub velocity: (f32, f32),
+    /// Remaining energy, between 0 and 1.
+    pub energy: f32,
+}
+
+impl Motion {
+    /// At rest, facing a random direction, with full energy.
+    pub fn random() -> Motion {
+        Motion {
+            heading: super::random::rng().gen::<f32>() * std::f32::consts::TAU,
+            velocity: (0.0, 0.0),
+            energy: 1.0,
+        }
+    }
+}
@@ -0,0 +1,62 @@
+//! The random number generator behind everything the simulation draws, so
+//! that a run can be seeded, and checkpointed and resumed exactly.
+//!
+//! The generator is per thread; only the simulation thread should use it.
+
+use std::cell::RefCell;
+
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use uuid::Uuid;
+
+thread_local! {
+    static RNG: RefCell<ChaCha8Rng> = RefCell::new(ChaCha8Rng::from_entropy());
+}
+
+/// Handle to this thread's generator, used like `rand::thread_rng()`.
+pub struct SimulationRng;
+
+pub fn rng() -> SimulationRng {
+    SimulationRng
+}
+
+pub fn seed(seed: u64) {
+    restore(ChaCha8Rng::seed_from_u64(seed));
+}
+
+/// A copy of the generator, to be restored later.
+pub fn state() -> ChaCha8Rng {
+    RNG.with(|rng| rng.borrow().clone())
+}
+
+pub fn restore(state: ChaCha8Rng) {
+    RNG.with(|rng| *rng.borrow_mut() = state);
+}
+
+/// A random (version 4) UUID drawn from the generator.
+pub fn uuid() -> Uuid {
+    let mut bytes = [0; 16];
+    rng().fill_bytes(&mut bytes);
+    uuid::Builder::from_bytes(bytes)
+        .set_variant(uuid::Variant::RFC4122)
+        .set_version(uuid::Version::Random)
+        .build()
+}
+
+impl RngCore for SimulationRng {
+    fn next_u32(&mut self) -> u32 {
+        RNG.with(|rng| rng.borrow_mut().next_u32())
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        RNG.with(|rng| rng.borrow_mut().next_u64())
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        RNG.with(|rng| rng.borrow_mut().fill_bytes(dest))
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        RNG.with(|rng| rng.borrow_mut().try_fill_bytes(dest))
+    }
+}
@@ -0,0 +1,99 @@
+//! Replay of logged generations in the viewer. Nothing evolves and nothing
+//! is logged; the agents are rebuilt from their genomes and simulated again,
+//! in the zones they were judged against if those were logged.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use log::info;
+use uuid::Uuid;
+
+use super::log_index::LogIndex;
+use super::log_reader::{Generation, LogError};
+use super::stats::GenerationStats;
+use super::trajectory::{self, GenerationTrajectory};
+use super::history::Header;
+use super::{viewer, Agent, Progress, SimulationMode, Zone};
+
+/// Replay `generation` forever, or every generation from it up to `to` once.
+pub fn replay(path: &str, generation: usize, to: Option<usize>) -> Result<(), LogError> {
+    let index = LogIndex::load_or_build(path)?;
+    let reader = index.reader_at(generation)?;
+    let run = reader.header().id;
+    let header = reader.header().copy();
+    // Trajectories are kept next to the log.
+    let directory = Path::new(path).parent().and_then(Path::to_str).unwrap_or("");
+    let recorded = trajectory::load(directory, run);
+    // Replays are for watching, so play them in real time.
+    let viewer = viewer::start_viewer(viewer::Playback { paused: false, speed: Some(1.0) });
+
+    match to {
+        None => {
+            let generation = reader.generations()
+                .take_while(|found| found.number <= generation)
+                .find(|found| found.number == generation)
+                .ok_or(LogError::NoGeneration(Some(generation)))?;
+            loop {
+                play(&generation, &header, recorded.get(&generation.number), &viewer);
+            }
+        }
+        Some(to) => {
+            let mut played = 0;
+            for generation in index.range(generation..=to)? {
+                play(&generation, &header, recorded.get(&generation.number), &viewer);
+                played += 1;
+            }
+            if played == 0 {
+                return Err(LogError::NoGeneration(Some(generation)));
+            }
+            Ok(())
+        }
+    }
+}
+
+fn play(generation: &Generation, header: &Header, recorded: Option<&GenerationTrajectory>, viewer: &viewer::ViewerHandle) {
+    let mut settings = generation.settings.clone();
+    settings.title = format!("Replay of generation {}", generation.number);
+
+    // Start where the agents started, if that was recorded.
+    let start: HashMap<Uuid, (f32, f32)> = recorded
+        .map(|recorded| recorded.agents.iter()
+            .filter_map(|agent| Some((agent.id, *agent.positions.first()?)))
+            .collect())
+        .unwrap_or_default();
+
+    let mut agents: Vec<Agent> = generation.agents.iter().cloned().map(|mut entry| {
+        header.migrate(&mut entry.genome);
+        let mut agent = Agent::from_log_entry(entry, settings.brain);
+        if let Some(position) = start.get(&agent.uuid) {
+            agent.position = *position;
+        }
+        agent
+    }).collect();
+
+    let zones = match recorded {
+        Some(recorded) => recorded.zones.clone(),
+        None => generation.zones.clone(),
+    };
+    if zones.is_empty() {
+        info!("no zones logged for generation {}, placing them randomly", generation.number);
+    }
+    let mut zones = zones.into_iter();
+    let SimulationMode::SafeZoneRace { radius_low, radius_high } = settings.mode;
+    let world_radius = settings.world_radius;
+    let mut place_zone = |_| zones.next()
+        .map(|placement| placement.zone)
+        .unwrap_or_else(|| Zone::random(world_radius, radius_low..radius_high));
+
+    info!("replaying generation {} ({} agents)", generation.number, agents.len());
+    let start = Progress::start(place_zone(0.0));
+    let started = std::time::Instant::now();
+    let progress = super::simulate_generation(&mut agents, &mut settings, &mut place_zone, viewer, None, start, &mut |_, _, _, _| ());
+    let zone = progress.zone;
+    viewer.publish(viewer::kill(&agents, &zone));
+    viewer.publish(viewer::Event::Summary(GenerationStats::compute(generation.number, &agents, &zone, progress.steps, started.elapsed())));
+
+    let survivors = agents.iter().filter(|agent| zone.contains(agent.position)).count();
+    let logged = generation.agents.iter().filter(|agent| agent.survived).count();
+    info!("{} survivors (logged: {})", survivors, logged);
+}
@@ -0,0 +1,212 @@
+//! Founding populations revived from one or more logs.
+//!
+//! On the command line a source is a log path, optionally followed by
+//! comma-separated options:
+//!
+//! - `generation=<n>`: the last complete generation up to `n`, instead of
+//!   the last complete one.
+//! - `survivors`: only the agents that survived that generation.
+//! - `agents=<id>+<id>...`: only these agents. Without a generation, the
+//!   log is searched for their last appearance.
+//! - `share=<weight>`: relative share of the founding population.
+//!
+//! A whole generation from a single source without a share is revived as it
+//! was. Otherwise the population is divided between the sources by share (1
+//! by default), each filling its part by repeating its agents as often as
+//! needed.
+
+use std::collections::HashSet;
+use std::str::FromStr;
+
+use log::{info, warn};
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+
+use super::brain::BrainLayout;
+use super::genetics::GENOME_LAYOUT;
+use super::history::{AgentEntry, Header, History};
+use super::log_reader::{LogError, LogReader};
+use super::trajectory::ZonePlacement;
+use super::{Agent, Settings};
+
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub log: String,
+    pub generation: Option<usize>,
+    pub survivors: bool,
+    pub agents: Vec<Uuid>,
+    pub share: Option<f32>,
+}
+
+impl FromStr for Source {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Source, String> {
+        let mut options = s.split(',');
+        let mut source = Source {
+            log: options.next().filter(|log| !log.is_empty()).ok_or("empty log path")?.to_string(),
+            generation: None,
+            survivors: false,
+            agents: vec![],
+            share: None,
+        };
+
+        for option in options {
+            match option.split_once('=') {
+                None if option == "survivors" => source.survivors = true,
+                Some(("generation", generation)) => {
+                    source.generation = Some(generation.parse().map_err(|e| format!("invalid generation {}: {}", generation, e))?);
+                }
+                Some(("agents", agents)) => {
+                    for agent in agents.split('+') {
+                        source.agents.push(agent.parse().map_err(|e| format!("invalid agent {}: {}", agent, e))?);
+                    }
+                }
+                Some(("share", share)) => {
+                    let share: f32 = share.parse().map_err(|e| format!("invalid share {}: {}", share, e))?;
+                    if !share.is_finite() || share <= 0.0 {
+                        return Err(format!("share must be positive, not {}", share));
+                    }
+                    source.share = Some(share);
+                }
+                _ => return Err(format!("unknown revive option {}", option)),
+            }
+        }
+        Ok(source)
+    }
+}
+
+/// Where part of a founding population came from.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Origin {
+    pub run: Uuid,
+    pub generation: usize,
+    pub survivors: bool,
+    /// The agents selected from the generation.
+    pub agents: Vec<Uuid>,
+    /// Number of founders descended from these agents.
+    pub founders: usize,
+    /// Zones the generation was judged against, if they were logged.
+    #[serde(default)]
+    pub zones: Vec<ZonePlacement>,
+}
+
+struct Selection {
+    origin: Origin,
+    settings: Settings,
+    entries: Vec<AgentEntry>,
+}
+
+/// Load the selected agents of a source, migrated to the current layout.
+fn select(source: &Source, generation: Option<usize>) -> Result<Selection, LogError> {
+    let generation = source.generation.or(generation);
+    info!("reviving {}, generation {:?}...", source.log, generation);
+
+    let wanted: HashSet<Uuid> = source.agents.iter().copied().collect();
+    let (number, settings, zones, mut entries, header) = if !wanted.is_empty() && generation.is_none() {
+        // Take the last appearance of each agent anywhere in the log.
+        let reader = LogReader::open(&source.log)?;
+        let header = reader.header().copy();
+        let mut found = None;
+        let mut entries: Vec<AgentEntry> = vec![];
+        for next in reader.generations() {
+            let matching: Vec<AgentEntry> = next.agents.into_iter().filter(|entry| wanted.contains(&entry.id)).collect();
+            if matching.is_empty() {
+                continue;
+            }
+            entries.retain(|entry| !matching.iter().any(|other| other.id == entry.id));
+            entries.extend(matching);
+            found = Some((next.number, next.settings, next.zones));
+        }
+        let (number, settings, zones) = found.ok_or(LogError::NoAgents)?;
+        (number, settings, zones, entries, header)
+    }
+    else {
+        let (found, header) = History::load_generation(&source.log, generation)?;
+        (found.number, found.settings, found.zones, found.agents, header)
+    };
+
+    if !wanted.is_empty() {
+        entries.retain(|entry| wanted.contains(&entry.id));
+        let missing = wanted.iter().filter(|id| !entries.iter().any(|entry| entry.id == **id)).count();
+        if missing > 0 {
+            warn!("{} of the requested agents aren't in {}", missing, source.log);
+        }
+    }
+    if source.survivors {
+        entries.retain(|entry| entry.survived);
+    }
+    if entries.is_empty() {
+        return Err(LogError::NoAgents);
+    }
+
+    if header.genome_layout != GENOME_LAYOUT || header.brain_layout != Some(BrainLayout::current()) {
+        info!("migrating genomes from genome layout {} and a different brain layout", header.genome_layout);
+    }
+    for entry in &mut entries {
+        header.migrate(&mut entry.genome);
+    }
+
+    Ok(Selection {
+        origin: Origin {
+            run: header.id,
+            generation: number,
+            survivors: source.survivors,
+            agents: entries.iter().map(|entry| entry.id).collect(),
+            founders: entries.len(),
+            zones,
+        },
+        settings,
+        entries,
+    })
+}
+
+/// Revive a founding population from `sources`, using `generation` for
+/// sources that don't name one. Settings are taken from the first source.
+pub fn revive(sources: &[Source], generation: Option<usize>) -> Result<(Vec<Agent>, Settings, Header), String> {
+    let mut selections = vec![];
+    for source in sources {
+        selections.push(select(source, generation).map_err(|e| format!("{}: {}", source.log, e))?);
+    }
+
+    // Genomes only mean the same under the same brain, sensors and movement.
+    let settings = selections[0].settings.clone();
+    for (source, selection) in sources.iter().zip(&selections).skip(1) {
+        let other = &selection.settings;
+        let differs = [
+            ("brain type", settings.brain != other.brain),
+            ("sensors", settings.sensors != other.sensors),
+            ("movement", settings.movement != other.movement),
+        ];
+        if let Some((setting, _)) = differs.into_iter().find(|(_, differs)| *differs) {
+            return Err(format!("{}: {}", source.log, LogError::Incompatible(setting)));
+        }
+    }
+    let mut agents = vec![];
+    if sources.len() == 1 && sources[0].share.is_none() && selections[0].entries.len() == settings.num_agents {
+        agents.extend(selections[0].entries.drain(..).map(|entry| Agent::from_log_entry(entry, settings.brain)));
+    }
+    else {
+        // Round the cumulative share, so that the parts add up exactly.
+        let shares: Vec<f32> = sources.iter().map(|source| source.share.unwrap_or(1.0)).collect();
+        let total: f32 = shares.iter().sum();
+        let mut cumulative = 0.0;
+        for (selection, share) in selections.iter_mut().zip(shares) {
+            cumulative += share;
+            let end = ((cumulative / total) * settings.num_agents as f32).round() as usize;
+            let count = end.min(settings.num_agents) - agents.len();
+            info!("{} founders from {} agents of run {}", count, selection.entries.len(), selection.origin.run);
+            selection.origin.founders = count;
+            agents.extend(selection.entries.iter().cycle().take(count).cloned().map(|entry| Agent::from_log_entry(entry, settings.brain)));
+        }
+    }
+
+    let first = &selections[0].origin;
+    let header = Header {
+        revived_from: Some(first.run),
+        revived_generation: Some(first.generation),
+        origins: selections.iter().map(|selection| selection.origin.clone()).collect(),
+        ..Header::new()
+    };
+    Ok((agents, settings, header))
+}
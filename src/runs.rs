@@ -0,0 +1,155 @@
+//! The graph of runs in an output directory: which run was revived from
+//! which, at which generation, and which runs restarted after extinction.
+//! A run revived from several sources appears under the first of them.
+
+use std::collections::HashMap;
+use std::io::Write;
+
+use log::warn;
+use uuid::Uuid;
+
+use super::log_index::LogIndex;
+use super::log_reader::{LogError, LogReader};
+
+pub struct Run {
+    pub id: Uuid,
+    pub revived_from: Option<Uuid>,
+    pub revived_generation: Option<usize>,
+    pub reseeded_from: Option<Uuid>,
+    /// Runs and generations a mixed founding population came from.
+    pub origins: Vec<(Uuid, usize)>,
+    pub started: Option<u64>,
+    pub path: String,
+    /// First and last generation started in the log.
+    pub generations: Option<(usize, usize)>,
+}
+
+impl Run {
+    fn load(path: &str) -> Result<Run, LogError> {
+        let reader = LogReader::open(path)?;
+        let header = reader.header();
+        let index = LogIndex::load_or_build(path)?;
+        let generations = index.generations().next().zip(index.generations().last());
+        Ok(Run {
+            id: header.id,
+            revived_from: header.revived_from,
+            revived_generation: header.revived_generation,
+            reseeded_from: header.reseeded_from,
+            origins: header.origins.iter().map(|origin| (origin.run, origin.generation)).collect(),
+            started: header.started,
+            path: path.to_string(),
+            generations,
+        })
+    }
+
+    /// The run this one was started from, if any. A reseeded run keeps the
+    /// revival of the run that went extinct in its header, but descends from
+    /// that run.
+    pub fn origin(&self) -> Option<Uuid> {
+        self.reseeded_from.or(self.revived_from)
+    }
+
+    /// Sources of a mixed founding population besides `origin`.
+    pub fn crossed_with(&self) -> &[(Uuid, usize)] {
+        match self.reseeded_from {
+            Some(_) => &[],
+            None => self.origins.get(1..).unwrap_or_default(),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        let mut description = match self.generations {
+            Some((first, last)) => format!("generations {}-{}", first, last),
+            None => "no generations".to_string(),
+        };
+        if let (Some(generation), None) = (self.revived_generation, self.reseeded_from) {
+            description += &format!(", revived at generation {}", generation);
+        }
+        for (run, generation) in self.crossed_with() {
+            description += &format!(", crossed with {} at generation {}", run, generation);
+        }
+        if let Some(extinct) = self.reseeded_from {
+            description += &format!(", reseeded after {} went extinct", extinct);
+        }
+        description
+    }
+}
+
+pub struct Runs {
+    /// In the order they were started.
+    runs: Vec<Run>,
+    children: HashMap<Uuid, Vec<usize>>,
+}
+
+impl Runs {
+    /// Load the headers of all logs in `dir`, skipping unreadable ones.
+    pub fn scan(dir: &str) -> Result<Runs, LogError> {
+        let mut runs = vec![];
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|extension| extension != "log") {
+                continue;
+            }
+
+            let path = path.to_string_lossy().to_string();
+            match Run::load(&path) {
+                Ok(run) => runs.push(run),
+                Err(error) => warn!("skipping {}: {}", path, error),
+            }
+        }
+        runs.sort_by(|a, b| (a.started, &a.path).cmp(&(b.started, &b.path)));
+
+        let mut children: HashMap<Uuid, Vec<usize>> = HashMap::new();
+        for (i, run) in runs.iter().enumerate() {
+            if let Some(origin) = run.origin() {
+                children.entry(origin).or_default().push(i);
+            }
+        }
+
+        Ok(Runs { runs, children })
+    }
+
+    pub fn runs(&self) -> &[Run] {
+        &self.runs
+    }
+
+    /// Runs that weren't started from another run in the directory.
+    pub fn roots(&self) -> impl Iterator<Item = &Run> {
+        self.runs.iter().filter(|run| run.origin().is_none_or(|origin| !self.runs.iter().any(|other| other.id == origin)))
+    }
+
+    pub fn children(&self, id: Uuid) -> impl Iterator<Item = &Run> {
+        self.children.get(&id).into_iter().flatten().map(|i| &self.runs[*i])
+    }
+
+    /// Print the runs as an indented tree.
+    pub fn write_tree(&self, file: &mut impl Write) -> std::io::Result<()> {
+        let mut pending: Vec<(&Run, usize)> = self.roots().map(|run| (run, 0)).collect();
+        pending.reverse();
+        while let Some((run, depth)) = pending.pop() {
+            write!(file, "{:indent$}{} {}", "", run.id, run.describe(), indent = depth * 2)?;
+            match run.origin() {
+                Some(origin) if depth == 0 => writeln!(file, " (from {}, not in directory)", origin)?,
+                _ => writeln!(file)?,
+            }
+
+            let children: Vec<&Run> = self.children(run.id).collect();
+            pending.extend(children.into_iter().rev().map(|child| (child, depth + 1)));
+        }
+        Ok(())
+    }
+}
+
+/// Print the graph of the runs in `dir`.
+pub fn print(dir: &str, dot: bool) -> Result<(), LogError> {
+    let runs = Runs::scan(dir)?;
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    if dot {
+        runs.draw_graph(&mut stdout);
+    }
+    else {
+        runs.write_tree(&mut stdout)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,185 @@
+//! Turns the state of the world into brain inputs.
+
+use std::collections::VecDeque;
+
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+
+use super::brain::{Controller, Input, NUM_INPUTS};
+use super::Zone;
+
+/// Which set of inputs agents are given. Recorded with the settings so that
+/// revived genomes see the same inputs they evolved with.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub enum Sensors {
+    /// Raw world coordinates and an unbounded oscillator; the original inputs.
+    #[default]
+    Raw,
+    /// Inputs relative to the agent, scaled by the world radius, plus sine
+    /// and cosine oscillators at the given frequencies (in Hz).
+    Normalized {
+        oscillator_frequencies: [f32; 2],
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ArgEnum)]
+pub enum SensorKind {
+    Raw,
+    Normalized,
+}
+
+pub const DEFAULT_OSCILLATOR_FREQUENCIES: [f32; 2] = [0.02, 0.5];
+
+impl Sensors {
+    pub fn new(kind: SensorKind, oscillator_frequencies: [f32; 2]) -> Sensors {
+        match kind {
+            SensorKind::Raw => Sensors::Raw,
+            SensorKind::Normalized => Sensors::Normalized { oscillator_frequencies },
+        }
+    }
+
+    /// The inputs this set measures, which excludes the constant.
+    pub fn measured(&self) -> &'static [Input] {
+        match self {
+            Sensors::Raw => &[Input::Oscillator, Input::X, Input::Y, Input::SafeX, Input::SafeY, Input::SafeRadius],
+            Sensors::Normalized { .. } => &[
+                Input::ZoneDX, Input::ZoneDY, Input::ZoneEdge, Input::WallDistance,
+                Input::Sin0, Input::Cos0, Input::Sin1, Input::Cos1,
+            ],
+        }
+    }
+
+    /// Compute the value of every input. Inputs that don't belong to the
+    /// selected set are left at zero.
+    pub fn read(&self, position: (f32, f32), time: f32, world_radius: f32, zone: &Zone) -> [f32; NUM_INPUTS] {
+        let mut inputs = [0.0; NUM_INPUTS];
+        inputs[Input::Constant as usize] = 1.0;
+
+        match self {
+            Sensors::Raw => {
+                inputs[Input::Oscillator as usize] = time * std::f32::consts::TAU;
+                inputs[Input::X as usize] = position.0;
+                inputs[Input::Y as usize] = position.1;
+                inputs[Input::SafeX as usize] = zone.x;
+                inputs[Input::SafeY as usize] = zone.y;
+                inputs[Input::SafeRadius as usize] = zone.radius;
+            }
+            Sensors::Normalized { oscillator_frequencies } => {
+                let dx = zone.x - position.0;
+                let dy = zone.y - position.1;
+                let zone_distance = (dx.powf(2.0) + dy.powf(2.0)).sqrt();
+                let center_distance = (position.0.powf(2.0) + position.1.powf(2.0)).sqrt();
+
+                inputs[Input::ZoneDX as usize] = dx / world_radius;
+                inputs[Input::ZoneDY as usize] = dy / world_radius;
+                // Negative while inside the zone.
+                inputs[Input::ZoneEdge as usize] = (zone_distance - zone.radius) / world_radius;
+                inputs[Input::WallDistance as usize] = (world_radius - center_distance) / world_radius;
+
+                let phase = |frequency: f32| time * frequency * std::f32::consts::TAU;
+                inputs[Input::Sin0 as usize] = phase(oscillator_frequencies[0]).sin();
+                inputs[Input::Cos0 as usize] = phase(oscillator_frequencies[0]).cos();
+                inputs[Input::Sin1 as usize] = phase(oscillator_frequencies[1]).sin();
+                inputs[Input::Cos1 as usize] = phase(oscillator_frequencies[1]).cos();
+            }
+        }
+
+        inputs
+    }
+}
+
+/// Imperfections of sensors and actuators. All zero means exact inputs and
+/// outputs, which is how agents were originally simulated.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Noise {
+    /// Standard deviation of Gaussian noise added to every measured input.
+    pub input_stddev: f32,
+    /// Probability that a measured input reads zero on any given step.
+    pub input_dropout: f32,
+    /// Standard deviation of Gaussian noise added to the speed outputs.
+    pub output_stddev: f32,
+    /// Number of steps by which inputs lag behind the world.
+    pub sensor_delay: usize,
+}
+
+impl Noise {
+    /// Perturb the inputs `sensors` measures, leaving the constant and
+    /// unused inputs alone.
+    pub fn apply_to_inputs(&self, inputs: &mut [f32; NUM_INPUTS], sensors: &Sensors) {
+        if self.input_stddev == 0.0 && self.input_dropout == 0.0 {
+            return;
+        }
+
+        let mut rng = super::random::rng();
+        for input in sensors.measured() {
+            let value = &mut inputs[*input as usize];
+            if rng.gen::<f32>() < self.input_dropout {
+                *value = 0.0;
+            }
+            else {
+                *value += rng.sample::<f32, _>(rand_distr::StandardNormal) * self.input_stddev;
+            }
+        }
+    }
+
+    pub fn apply_to_output(&self, value: f32) -> f32 {
+        if self.output_stddev == 0.0 {
+            return value;
+        }
+        value + super::random::rng().sample::<f32, _>(rand_distr::StandardNormal) * self.output_stddev
+    }
+}
+
+/// Past sensor readings, for delaying inputs.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DelayLine {
+    buffer: VecDeque<[f32; NUM_INPUTS]>,
+}
+
+impl DelayLine {
+    pub fn new() -> DelayLine {
+        DelayLine {
+            buffer: VecDeque::new(),
+        }
+    }
+
+    /// Record this step's reading and return the one from `steps` steps ago.
+    /// Until enough steps have passed, the oldest reading is returned.
+    pub fn delay(&mut self, inputs: [f32; NUM_INPUTS], steps: usize) -> [f32; NUM_INPUTS] {
+        self.buffer.push_back(inputs);
+        while self.buffer.len() > steps + 1 {
+            self.buffer.pop_front();
+        }
+        self.buffer[0]
+    }
+}
+
+pub fn feed(brain: &mut dyn Controller, inputs: &[f32; NUM_INPUTS]) {
+    for (i, value) in inputs.iter().enumerate() {
+        brain.input(num::FromPrimitive::from_usize(i).unwrap(), *value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn noise_only_perturbs_measured_inputs() {
+        let noise = Noise { input_stddev: 1.0, input_dropout: 0.5, ..Noise::default() };
+        let zone = Zone { x: 10.0, y: -20.0, radius: 50.0 };
+        for sensors in [Sensors::Raw, Sensors::Normalized { oscillator_frequencies: DEFAULT_OSCILLATOR_FREQUENCIES }] {
+            for step in 0..100 {
+                let mut inputs = sensors.read((1.0, 2.0), step as f32 * 0.05, 500.0, &zone);
+                noise.apply_to_inputs(&mut inputs, &sensors);
+                assert_eq!(inputs[Input::Constant as usize], 1.0);
+                for (i, value) in inputs.iter().enumerate() {
+                    let measured = sensors.measured().iter().any(|input| *input as usize == i);
+                    if i != Input::Constant as usize && !measured {
+                        assert_eq!(*value, 0.0, "input {} of {:?}", i, sensors);
+                    }
+                }
+            }
+        }
+    }
+}
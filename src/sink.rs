@@ -0,0 +1,89 @@
+//! Destinations for the streams a run writes.
+//!
+//! On the command line a sink is `none`, `-` for stdout, `tcp:<address>`,
+//! `unix:<path>`, or otherwise a directory in which files named after the
+//! run are created.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sink {
+    /// Discard everything.
+    None,
+    /// Files in a directory, which is created if missing.
+    Directory(String),
+    Stdout,
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(String),
+}
+
+impl FromStr for Sink {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Sink, String> {
+        let sink = match s {
+            "" => return Err("empty sink".to_string()),
+            "none" => Sink::None,
+            "-" => Sink::Stdout,
+            _ => match s.split_once(':') {
+                Some(("tcp", address)) => Sink::Tcp(address.to_string()),
+                #[cfg(unix)]
+                Some(("unix", path)) => Sink::Unix(path.to_string()),
+                _ => Sink::Directory(s.to_string()),
+            }
+        };
+        Ok(sink)
+    }
+}
+
+impl Sink {
+    /// Open a stream, named `name` if it's a file.
+    pub fn open(&self, name: &str) -> io::Result<Box<dyn Write>> {
+        Ok(match self {
+            Sink::None => Box::new(io::sink()),
+            Sink::Directory(directory) => {
+                std::fs::create_dir_all(directory)?;
+                Box::new(BufWriter::new(File::create(Path::new(directory).join(name))?))
+            }
+            Sink::Stdout => Box::new(BufWriter::new(io::stdout())),
+            Sink::Tcp(address) => Box::new(BufWriter::new(TcpStream::connect(address)?)),
+            #[cfg(unix)]
+            Sink::Unix(path) => Box::new(BufWriter::new(std::os::unix::net::UnixStream::connect(path)?)),
+        })
+    }
+
+    /// Continue a stream after its first `length` bytes. Files in a
+    /// directory are truncated to that length; other sinks start over.
+    pub fn append(&self, name: &str, length: u64) -> io::Result<Box<dyn Write>> {
+        match self {
+            Sink::Directory(directory) => {
+                let mut file = File::options().write(true).open(Path::new(directory).join(name))?;
+                file.set_len(length)?;
+                file.seek(SeekFrom::End(0))?;
+                Ok(Box::new(BufWriter::new(file)))
+            }
+            _ => self.open(name),
+        }
+    }
+
+    /// Open a companion file, such as an index, which is only kept next to
+    /// files in a directory.
+    pub fn open_companion(&self, name: &str) -> io::Result<Box<dyn Write>> {
+        match self {
+            Sink::Directory(_) => self.open(name),
+            _ => Ok(Box::new(io::sink())),
+        }
+    }
+
+    pub fn directory(&self) -> Option<&str> {
+        match self {
+            Sink::Directory(directory) => Some(directory),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! Summary statistics of a generation, logged so that learning curves can be
+//! plotted without going through the genomes.
+
+use std::io::Write;
+use std::time::Duration;
+
+use serde::{Serialize, Deserialize};
+
+use super::genetics::{NUM_CODONS, WEIGHT_CODONS};
+use super::log_reader::{LogError, LogReader};
+use super::{Agent, Zone};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Distribution {
+    pub min: f32,
+    pub lower_quartile: f32,
+    pub median: f32,
+    pub upper_quartile: f32,
+    pub max: f32,
+}
+
+impl Distribution {
+    fn of(mut values: Vec<f32>) -> Distribution {
+        if values.is_empty() {
+            values.push(f32::NAN);
+        }
+        values.sort_by(|a, b| a.total_cmp(b));
+        let quantile = |q: f32| values[((values.len() - 1) as f32 * q).round() as usize];
+        Distribution {
+            min: quantile(0.0),
+            lower_quartile: quantile(0.25),
+            median: quantile(0.5),
+            upper_quartile: quantile(0.75),
+            max: quantile(1.0),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GenerationStats {
+    pub generation: usize,
+    pub agents: usize,
+    pub survivors: usize,
+    pub survival_rate: f32,
+    /// Fitness is 1 inside the zone and falls off with the distance outside
+    /// it, so that near misses can be told apart from agents that never got
+    /// close.
+    pub fitness_mean: f32,
+    pub fitness_variance: f32,
+    /// Standard deviation of each codon across the population, averaged over
+    /// all codons.
+    pub diversity: f32,
+    pub mean_absolute_weight: f32,
+    /// Final distance of the agents to the center of the zone.
+    pub zone_distance: Distribution,
+    /// Seconds spent simulating the generation.
+    pub wall_time: f32,
+    pub steps_per_second: f32,
+}
+
+pub fn fitness(zone: &Zone, position: (f32, f32)) -> f32 {
+    let distance = zone.distance(position);
+    if distance < zone.radius {
+        1.0
+    }
+    else {
+        zone.radius / distance
+    }
+}
+
+fn mean_and_variance(values: impl Iterator<Item = f32> + Clone) -> (f32, f32) {
+    let count = values.clone().count().max(1) as f32;
+    let mean = values.clone().sum::<f32>() / count;
+    let variance = values.map(|value| (value - mean).powf(2.0)).sum::<f32>() / count;
+    (mean, variance)
+}
+
+impl GenerationStats {
+    /// Summarize a generation that has just been simulated for `steps` steps,
+    /// before selection.
+    pub fn compute(generation: usize, agents: &[Agent], zone: &Zone, steps: usize, wall_time: Duration) -> GenerationStats {
+        let survivors = agents.iter().filter(|agent| zone.contains(agent.position)).count();
+        let (fitness_mean, fitness_variance) = mean_and_variance(agents.iter().map(|agent| fitness(zone, agent.position)));
+
+        let diversity = (0..NUM_CODONS)
+            .map(|i| mean_and_variance(agents.iter().map(|agent| agent.genome[i])).1.sqrt())
+            .sum::<f32>() / NUM_CODONS as f32;
+
+        let weights = WEIGHT_CODONS.len() * agents.len().max(1);
+        let mean_absolute_weight = agents.iter()
+            .flat_map(|agent| agent.genome[WEIGHT_CODONS].iter())
+            .map(|weight| weight.abs())
+            .sum::<f32>() / weights as f32;
+
+        let zone_distance = Distribution::of(agents.iter()
+            .map(|agent| zone.distance(agent.position))
+            .collect());
+
+        let wall_time = wall_time.as_secs_f32();
+        GenerationStats {
+            generation,
+            agents: agents.len(),
+            survivors,
+            survival_rate: survivors as f32 / agents.len().max(1) as f32,
+            fitness_mean,
+            fitness_variance,
+            diversity,
+            mean_absolute_weight,
+            zone_distance,
+            wall_time,
+            steps_per_second: steps as f32 / wall_time,
+        }
+    }
+
+    pub fn write_csv_header(write: &mut impl Write) -> std::io::Result<()> {
+        writeln!(
+            write,
+            "generation,agents,survivors,survival_rate,fitness_mean,fitness_variance,diversity,mean_absolute_weight,\
+            zone_distance_min,zone_distance_lower_quartile,zone_distance_median,zone_distance_upper_quartile,zone_distance_max,\
+            wall_time,steps_per_second",
+        )
+    }
+
+    pub fn write_csv(&self, write: &mut impl Write) -> std::io::Result<()> {
+        writeln!(
+            write,
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            self.generation,
+            self.agents,
+            self.survivors,
+            self.survival_rate,
+            self.fitness_mean,
+            self.fitness_variance,
+            self.diversity,
+            self.mean_absolute_weight,
+            self.zone_distance.min,
+            self.zone_distance.lower_quartile,
+            self.zone_distance.median,
+            self.zone_distance.upper_quartile,
+            self.zone_distance.max,
+            self.wall_time,
+            self.steps_per_second,
+        )
+    }
+}
+
+/// Print the statistics records of a log as CSV, without decoding genomes.
+pub fn print_csv(path: &str) -> Result<(), LogError> {
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    GenerationStats::write_csv_header(&mut stdout)?;
+
+    for generation in LogReader::open(path)?.without_agents().generations() {
+        if let Some(stats) = generation.stats {
+            stats.write_csv(&mut stdout)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,197 @@
+//! Optional recording of agent trajectories, for offline analysis and replay.
+//!
+//! Trajectories are written to `<run id>.trajectories` in the log directory
+//! (`output` if the log isn't written to one), one JSON line per recorded
+//! generation. Which generations are recorded is chosen on the command line;
+//! in addition, creating the file `<run id>.record` next to it records the
+//! next generation.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+
+use super::{Agent, Zone};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ZonePlacement {
+    /// Simulation time at which the zone was placed.
+    pub time: f32,
+    pub zone: Zone,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AgentTrajectory {
+    pub id: Uuid,
+    pub positions: Vec<(f32, f32)>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GenerationTrajectory {
+    pub run: Uuid,
+    pub generation: usize,
+    /// Simulation time of every sample.
+    pub times: Vec<f32>,
+    pub zones: Vec<ZonePlacement>,
+    pub agents: Vec<AgentTrajectory>,
+}
+
+/// A generation being recorded, saved in checkpoints so that resuming
+/// continues the recording.
+#[derive(Serialize, Deserialize)]
+pub struct Recording {
+    pub trajectory: GenerationTrajectory,
+    pub steps: usize,
+}
+
+pub struct Selection {
+    /// Record every generation divisible by this.
+    pub every: Option<usize>,
+    /// Record this generation, the last of the run.
+    pub last: Option<usize>,
+    /// Steps between samples.
+    pub interval: usize,
+}
+
+pub struct Recorder {
+    run: Uuid,
+    directory: String,
+    selection: Selection,
+    write: Option<BufWriter<File>>,
+    current: Option<GenerationTrajectory>,
+    steps: usize,
+}
+
+pub fn trajectory_path(directory: &str, run: Uuid) -> PathBuf {
+    Path::new(directory).join(format!("{}.trajectories", run))
+}
+
+fn request_path(directory: &str, run: Uuid) -> PathBuf {
+    Path::new(directory).join(format!("{}.record", run))
+}
+
+/// Load the recorded trajectories of a run, if there are any.
+pub fn load(directory: &str, run: Uuid) -> HashMap<usize, GenerationTrajectory> {
+    let mut trajectories = HashMap::new();
+    let file = match File::open(trajectory_path(directory, run)) {
+        Ok(file) => file,
+        Err(_) => return trajectories,
+    };
+
+    for line in BufReader::new(file).lines() {
+        match line.map(|line| serde_json::from_str::<GenerationTrajectory>(&line)) {
+            Ok(Ok(trajectory)) => { trajectories.insert(trajectory.generation, trajectory); }
+            // A partially written last line.
+            _ => break,
+        }
+    }
+    trajectories
+}
+
+impl Recorder {
+    pub fn new(run: Uuid, selection: Selection, directory: &str) -> Recorder {
+        Recorder {
+            run,
+            directory: directory.to_string(),
+            selection,
+            write: None,
+            current: None,
+            steps: 0,
+        }
+    }
+
+    fn requested(&self) -> bool {
+        std::fs::remove_file(request_path(&self.directory, self.run)).is_ok()
+    }
+
+    /// Start recording `generation` if it's selected.
+    pub fn begin(&mut self, generation: usize, agents: &[Agent], zone: &Zone) {
+        let selected = self.selection.every.is_some_and(|every| every > 0 && generation.is_multiple_of(every))
+            || self.selection.last == Some(generation)
+            || self.requested();
+        if !selected {
+            return;
+        }
+
+        info!("recording trajectories of generation {}", generation);
+        self.steps = 0;
+        self.current = Some(GenerationTrajectory {
+            run: self.run,
+            generation,
+            times: vec![],
+            zones: vec![],
+            agents: agents.iter().map(|agent| AgentTrajectory {
+                id: agent.uuid,
+                positions: vec![],
+            }).collect(),
+        });
+        self.zone(0.0, zone);
+        self.sample(0.0, agents);
+    }
+
+    /// The generation being recorded, if any.
+    pub fn recording(&self) -> Option<Recording> {
+        self.current.as_ref().map(|current| Recording { trajectory: current.clone(), steps: self.steps })
+    }
+
+    /// Continue a recording saved by `recording`.
+    pub fn resume(&mut self, recording: Recording) {
+        info!("resuming trajectories of generation {}", recording.trajectory.generation);
+        self.steps = recording.steps;
+        self.current = Some(recording.trajectory);
+    }
+
+    pub fn zone(&mut self, time: f32, zone: &Zone) {
+        if let Some(current) = &mut self.current {
+            current.zones.push(ZonePlacement { time, zone: zone.clone() });
+        }
+    }
+
+    /// Call after every step of the simulation.
+    pub fn step(&mut self, time: f32, agents: &[Agent]) {
+        self.steps += 1;
+        if self.steps.is_multiple_of(self.selection.interval.max(1)) {
+            self.sample(time, agents);
+        }
+    }
+
+    fn sample(&mut self, time: f32, agents: &[Agent]) {
+        if let Some(current) = &mut self.current {
+            current.times.push(time);
+            for (trajectory, agent) in current.agents.iter_mut().zip(agents) {
+                trajectory.positions.push(agent.position);
+            }
+        }
+    }
+
+    /// Finish the generation, writing it out if it was recorded.
+    pub fn end(&mut self, time: f32, agents: &[Agent]) {
+        if self.current.is_none() {
+            return;
+        }
+
+        // Always include the final positions.
+        if self.current.as_ref().unwrap().times.last() != Some(&time) {
+            self.sample(time, agents);
+        }
+
+        let current = self.current.take().unwrap();
+        if let Err(error) = self.write(&current) {
+            warn!("couldn't write trajectories: {}", error);
+        }
+    }
+
+    fn write(&mut self, trajectory: &GenerationTrajectory) -> std::io::Result<()> {
+        if self.write.is_none() {
+            self.write = Some(BufWriter::new(File::options().create(true).append(true).open(trajectory_path(&self.directory, self.run))?));
+        }
+
+        let write = self.write.as_mut().unwrap();
+        writeln!(write, "{}", serde_json::to_string(trajectory)?)?;
+        write.flush()
+    }
+}
@@ -1,58 +1,506 @@
+use std::cell::{Cell, RefCell};
+use std::io::ErrorKind;
 use std::net::{SocketAddr, TcpStream};
-use std::sync::mpsc::{Sender, Receiver, channel};
-use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Sender, SyncSender, Receiver, RecvTimeoutError, channel, sync_channel};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
 use log::{info, error, warn, debug};
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
 use websocket::OwnedMessage;
 use websocket::sync::Client;
+use websocket::ws::Message;
 use websocket::{
     sync::Server, server::NoTlsAcceptor
 };
 
+use super::dot::Graph;
+use super::stats::GenerationStats;
+
+/// Position, heading and velocity of an agent: `(x, y, heading, vx, vy)`.
+pub type AgentState = (f32, f32, f32, f32, f32);
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Event {
-    Frame(Vec<(f32, f32)>),
+    /// The agents at a simulation time, sampled every `frame_interval`.
+    Frame {
+        time: f32,
+        agents: Vec<AgentState>,
+    },
     Clear,
     Spawn(Vec<(Uuid, Vec<f32>)>),
-    Kill(Vec<usize>),
+    /// The outcome of selection at the end of a generation.
+    Kill {
+        /// Indices, in the order the agents were spawned, of those that died.
+        died: Vec<usize>,
+        survivors: usize,
+        /// The zone they were judged against.
+        zone: super::Zone,
+    },
+    /// Statistics of a generation, after `Kill`.
+    Summary(GenerationStats),
     Settings(super::Settings),
+    /// Reply to `Request::Snapshot`.
+    Snapshot(Snapshot),
+    /// Sent whenever playback changes, and to new clients.
+    Playback(Playback),
+    /// Reply to `Request::Describe`.
+    Agent(AgentDescription),
+    /// Reply to a request that couldn't be answered.
+    Error(String),
+}
+
+/// Requests from clients, encoded like events: `"Pause"`, `{"Speed": 2.0}`.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Request {
+    Pause,
+    Resume,
+    /// Simulate a single step, then pause.
+    Step,
+    /// Simulate at this multiple of real time, or as fast as possible if
+    /// `null`.
+    Speed(Option<f32>),
+    /// Simulate the rest of the generation without waiting or sending frames.
+    SkipGeneration,
+    Snapshot,
+    /// Ask for an `AgentDescription`, with its graph as DOT text if `dot`.
+    Describe {
+        agent: Uuid,
+        #[serde(default)]
+        dot: bool,
+    },
+}
+
+/// Multiples of real time that playback can be slowed down or sped up to.
+pub const SPEEDS: std::ops::RangeInclusive<f32> = 0.01..=1000.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct Playback {
+    pub paused: bool,
+    /// Multiple of real time, or `None` for as fast as possible.
+    pub speed: Option<f32>,
+}
+
+impl Playback {
+    /// Play at `speed`, unless it's outside `SPEEDS`.
+    fn set_speed(&mut self, speed: Option<f32>) -> Result<(), String> {
+        match speed {
+            Some(speed) if !SPEEDS.contains(&speed) => {
+                Err(format!("speed must be between {} and {}, not {}", SPEEDS.start(), SPEEDS.end(), speed))
+            }
+            _ => {
+                self.speed = speed;
+                Ok(())
+            }
+        }
+    }
 }
 
-pub fn spawn(agents: &Vec<super::Agent>) -> Event {
-    Event::Spawn(agents.into_iter().map(|agent| (agent.uuid, Vec::from(agent.genome.to_vec()))).collect())
+/// The current state of the world, with agent ids in case the client
+/// missed their spawn.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Snapshot {
+    pub time: f32,
+    pub zone: super::Zone,
+    pub agents: Vec<(Uuid, AgentState)>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AgentDescription {
+    pub id: Uuid,
+    /// Parent, grandparent and so on, as far as this run has seen them.
+    pub ancestry: Vec<Uuid>,
+    pub genome: Vec<f32>,
+    pub activations: Vec<f32>,
+    pub graph: Graph,
+    pub dot: Option<String>,
 }
 
-pub fn frame(agents: &Vec<super::Agent>) -> Event {
-    Event::Frame(agents.into_iter().map(|agent| agent.position).collect())
+/// What the simulation should do after `ViewerHandle::pace`.
+#[derive(Debug, PartialEq)]
+pub enum Control {
+    Continue,
+    SkipGeneration,
+}
+
+pub fn spawn(agents: &[super::Agent]) -> Event {
+    Event::Spawn(agents.iter().map(|agent| (agent.uuid, agent.genome.to_vec())).collect())
+}
+
+fn agent_state(agent: &super::Agent) -> AgentState {
+    (
+        agent.position.0,
+        agent.position.1,
+        agent.motion.heading,
+        agent.motion.velocity.0,
+        agent.motion.velocity.1,
+    )
+}
+
+/// The latest frame, which the simulation only provides when the viewer
+/// thread wants one.
+#[derive(Default)]
+pub struct Sampler {
+    wanted: AtomicBool,
+    frame: Mutex<Option<Event>>,
+}
+
+pub fn kill(agents: &[super::Agent], zone: &super::Zone) -> Event {
+    let died: Vec<usize> = (0..agents.len()).filter(|i| !zone.contains(agents[*i].position)).collect();
+    Event::Kill {
+        survivors: agents.len() - died.len(),
+        died,
+        zone: zone.clone(),
+    }
+}
+
+pub fn snapshot(agents: &[super::Agent], progress: &super::Progress) -> Event {
+    Event::Snapshot(Snapshot {
+        time: progress.time,
+        zone: progress.zone.clone(),
+        agents: agents.iter().map(|agent| (agent.uuid, agent_state(agent))).collect(),
+    })
 }
 
 type ViewerServer = Server<NoTlsAcceptor>;
 type ViewerClient = Client<TcpStream>;
 
+/// Websocket subprotocols a client can ask for on connect. Clients that ask
+/// for neither get JSON.
+pub const JSON_PROTOCOL: &str = "evolution.json";
+pub const BINARY_PROTOCOL: &str = "evolution.binary";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    /// Every event as JSON text.
+    Json,
+    /// Frames as binary messages from `encode_frame`, other events as JSON.
+    Binary,
+}
+
+fn encode(event: &Event, encoding: Encoding, world_radius: f32) -> websocket::Message<'static> {
+    match (event, encoding) {
+        (Event::Frame { time, agents }, Encoding::Binary) => websocket::Message::binary(encode_frame(*time, agents, world_radius)),
+        _ => websocket::Message::text(serde_json::to_string(event).unwrap()),
+    }
+}
+
+/// Events the simulation may get ahead of the viewer thread by.
+const EVENT_CAPACITY: usize = 256;
+
+/// Messages a client may fall behind by before it is disconnected.
+const MAX_QUEUED: usize = 1024;
+
+/// An encoded message, possibly partially written.
+struct Queued {
+    frame: bool,
+    bytes: Vec<u8>,
+    written: usize,
+}
+
+struct Connection {
+    client: ViewerClient,
+    encoding: Encoding,
+    /// Messages not yet written, so that a slow client doesn't hold up the
+    /// others.
+    queue: VecDeque<Queued>,
+    /// Frames replaced by newer ones before they were written.
+    dropped: usize,
+    reported: usize,
+}
+
+impl Connection {
+    fn new(client: ViewerClient, encoding: Encoding) -> Connection {
+        Connection {
+            client,
+            encoding,
+            queue: VecDeque::new(),
+            dropped: 0,
+            reported: 0,
+        }
+    }
+
+    /// Queue a message, dropping any frame that hasn't started to be written.
+    fn queue(&mut self, message: &websocket::Message, frame: bool) {
+        if frame {
+            let queued = self.queue.len();
+            self.queue.retain(|stale| !stale.frame || stale.written > 0);
+            self.dropped += queued - self.queue.len();
+        }
+        let mut bytes = vec![];
+        message.serialize(&mut bytes, false).unwrap();
+        self.queue.push_back(Queued { frame, bytes, written: 0 });
+    }
+
+    /// Write as much as the client takes without blocking. Returns false if
+    /// the connection is lost or the client has fallen too far behind.
+    fn flush(&mut self) -> bool {
+        while let Some(next) = self.queue.front_mut() {
+            match self.client.writer_mut().write(&next.bytes[next.written..]) {
+                Ok(0) => return false,
+                Ok(written) => {
+                    next.written += written;
+                    if next.written == next.bytes.len() {
+                        self.queue.pop_front();
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(_) => return false,
+            }
+        }
+        self.queue.len() <= MAX_QUEUED
+    }
+}
+
+const FRAME_MESSAGE: u8 = 1;
+
+/// Encode a frame as a `u8` message type of 1, a little-endian `u32` agent
+/// count, and little-endian `f32` time, position and velocity scales. Then, per
+/// agent, `x`, `y`, `vx` and `vy` as little-endian `i16`s in units of the
+/// scale divided by `i16::MAX`, and the heading as a `u16` fraction of a turn.
+/// Positions are scaled by the world radius, velocities by the largest
+/// component in the frame.
+pub fn encode_frame(time: f32, frame: &[AgentState], world_radius: f32) -> Vec<u8> {
+    let velocity_scale = frame.iter()
+        .map(|(_, _, _, vx, vy)| vx.abs().max(vy.abs()))
+        .fold(0.0, f32::max)
+        .max(f32::EPSILON);
+    let quantize = |value: f32, scale: f32| ((value / scale).clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+    let turn = 2.0 * std::f32::consts::PI;
+
+    let mut message = Vec::with_capacity(17 + frame.len() * 10);
+    message.push(FRAME_MESSAGE);
+    message.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+    message.extend_from_slice(&time.to_le_bytes());
+    message.extend_from_slice(&world_radius.to_le_bytes());
+    message.extend_from_slice(&velocity_scale.to_le_bytes());
+    for (x, y, heading, vx, vy) in frame {
+        message.extend_from_slice(&quantize(*x, world_radius).to_le_bytes());
+        message.extend_from_slice(&quantize(*y, world_radius).to_le_bytes());
+        message.extend_from_slice(&quantize(*vx, velocity_scale).to_le_bytes());
+        message.extend_from_slice(&quantize(*vy, velocity_scale).to_le_bytes());
+        let heading = (heading.rem_euclid(turn) / turn * 65536.0) as u32 as u16;
+        message.extend_from_slice(&heading.to_le_bytes());
+    }
+    message
+}
+
+/// An event for all clients, or a reply to one.
+pub struct Outgoing {
+    to: Option<SocketAddr>,
+    event: Event,
+}
+
 struct Viewer {
     server: ViewerServer,
-    clients: HashMap<SocketAddr, ViewerClient>,
+    clients: HashMap<SocketAddr, Connection>,
     agents: Vec<(Uuid, Vec<f32>)>,
     settings: Option<super::Settings>,
+    /// Summaries of all generations so far, for charts of new clients.
+    summaries: Vec<GenerationStats>,
+    playback: Playback,
+    requests: Sender<(SocketAddr, Request)>,
+    sampler: Arc<Sampler>,
+    last_frame: Instant,
+    last_report: Instant,
 }
 
 pub enum ViewerHandle {
-    Mpsc(std::sync::mpsc::Sender<Event>),
+    Mpsc {
+        events: SyncSender<Outgoing>,
+        /// Whether the viewer thread has stopped.
+        lost: Cell<bool>,
+        requests: Receiver<(SocketAddr, Request)>,
+        playback: Cell<Playback>,
+        /// When the next step is due at the requested speed.
+        next_step: Cell<Option<Instant>>,
+        sampler: Arc<Sampler>,
+        /// Parents of the agents spawned so far, pruned to the ancestry of
+        /// the current ones.
+        parents: RefCell<HashMap<Uuid, Option<Uuid>>>,
+    },
     Disabled,
 }
 
 impl ViewerHandle {
     pub fn publish(&self, event: Event) {
-        match self {
-            ViewerHandle::Mpsc(sender) => { sender.send(event).unwrap() }
-            _ => {}
+        self.send(Outgoing { to: None, event });
+    }
+
+    pub fn reply(&self, to: SocketAddr, event: Event) {
+        self.send(Outgoing { to: Some(to), event });
+    }
+
+    /// Pass an event to the viewer thread, waiting for room if it's behind.
+    /// Only frames, which don't come through here, are ever dropped.
+    fn send(&self, outgoing: Outgoing) {
+        let ViewerHandle::Mpsc { events, lost, .. } = self else {
+            return;
+        };
+        if !lost.get() && events.send(outgoing).is_err() {
+            self.lose();
+        }
+    }
+
+    fn lose(&self) {
+        if let ViewerHandle::Mpsc { lost, .. } = self {
+            error!("viewer stopped, continuing without it");
+            lost.set(true);
+        }
+    }
+
+    /// Provide a frame if the viewer thread is due to send one.
+    pub fn sample(&self, agents: &[super::Agent], time: f32) {
+        if let ViewerHandle::Mpsc { sampler, .. } = self {
+            if sampler.wanted.swap(false, Ordering::Relaxed) {
+                let agents = agents.iter().map(agent_state).collect();
+                *sampler.frame.lock().unwrap() = Some(Event::Frame { time, agents });
+            }
+        }
+    }
+
+    /// Publish the agents of a new generation, remembering their parents.
+    pub fn spawn(&self, agents: &[super::Agent]) {
+        let ViewerHandle::Mpsc { parents, .. } = self else {
+            return;
+        };
+
+        let mut parents = parents.borrow_mut();
+        for agent in agents {
+            parents.insert(agent.uuid, agent.parent);
+        }
+
+        // Forget agents that aren't ancestors of the current ones.
+        let mut ancestors = HashSet::new();
+        for agent in agents {
+            let mut next = Some(agent.uuid);
+            while let Some(id) = next.filter(|id| ancestors.insert(*id)) {
+                next = parents.get(&id).copied().flatten();
+            }
         }
+        parents.retain(|id, _| ancestors.contains(id));
+
+        self.publish(spawn(agents));
+    }
+
+    fn ancestry(&self, id: Uuid) -> Vec<Uuid> {
+        let ViewerHandle::Mpsc { parents, .. } = self else {
+            return vec![];
+        };
+
+        let parents = parents.borrow();
+        let mut ancestry = vec![];
+        let mut next = parents.get(&id).copied().flatten();
+        while let Some(id) = next {
+            ancestry.push(id);
+            next = parents.get(&id).copied().flatten();
+        }
+        ancestry
+    }
+
+    fn describe(&self, agents: &[super::Agent], id: Uuid, dot: bool) -> Event {
+        let Some(agent) = agents.iter().find(|agent| agent.uuid == id) else {
+            return Event::Error(format!("no agent {}", id));
+        };
+
+        // Both kinds of controller have the weights and biases of a `Brain`.
+        let brain = super::genetics::create_brain(&agent.genome);
+        let dot = dot.then(|| {
+            let mut dot = vec![];
+            brain.draw_graph(&mut dot);
+            String::from_utf8(dot).unwrap()
+        });
+        Event::Agent(AgentDescription {
+            id,
+            ancestry: self.ancestry(id),
+            genome: agent.genome.to_vec(),
+            activations: agent.brain.activations(),
+            graph: brain.graph(),
+            dot,
+        })
+    }
+
+    /// Handle requests from clients and, if they asked for a speed, wait
+    /// until the next step of `time_step` seconds is due. While paused, this
+    /// blocks until a client resumes or steps.
+    pub fn pace(&self, time_step: f32, agents: &[super::Agent], progress: &super::Progress) -> Control {
+        let ViewerHandle::Mpsc { requests, playback: current, next_step, lost, .. } = self else {
+            return Control::Continue;
+        };
+        if lost.get() {
+            return Control::Continue;
+        }
+
+        let mut playback = current.get();
+        let mut step = false;
+        let mut control = Control::Continue;
+        loop {
+            let waiting = playback.paused && !step && control == Control::Continue;
+            let received = if waiting {
+                match requests.recv_timeout(Duration::from_millis(100)) {
+                    Ok(received) => Some(received),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => {
+                        // Nobody is left to resume.
+                        self.lose();
+                        playback.paused = false;
+                        break;
+                    }
+                }
+            }
+            else {
+                requests.try_recv().ok()
+            };
+
+            let Some((from, request)) = received else {
+                if waiting { continue } else { break }
+            };
+            debug!("request from {}: {:?}", from, request);
+            match request {
+                Request::Pause => playback.paused = true,
+                Request::Resume => playback.paused = false,
+                Request::Step => {
+                    playback.paused = true;
+                    step = true;
+                }
+                Request::Speed(speed) => {
+                    if let Err(error) = playback.set_speed(speed) {
+                        warn!("ignoring request from {}: {}", from, error);
+                        self.reply(from, Event::Error(error));
+                    }
+                }
+                Request::SkipGeneration => control = Control::SkipGeneration,
+                Request::Snapshot => self.reply(from, snapshot(agents, progress)),
+                Request::Describe { agent, dot } => self.reply(from, self.describe(agents, agent, dot)),
+            }
+        }
+
+        if playback != current.get() {
+            current.set(playback);
+            self.publish(Event::Playback(playback));
+        }
+        match playback.speed.filter(|_| control == Control::Continue) {
+            Some(speed) => {
+                // After pauses and slow steps, start over rather than racing to
+                // catch up.
+                let now = Instant::now();
+                let due = next_step.get().filter(|due| now < *due + Duration::from_millis(100)).unwrap_or(now);
+                if now < due {
+                    std::thread::sleep(due - now);
+                }
+                next_step.set(Some(due + Duration::from_secs_f32(time_step / speed)));
+            }
+            None => next_step.set(None),
+        }
+        control
     }
 }
 
 impl Viewer {
-    fn new(addr: &str) -> Viewer {
+    fn new(addr: &str, requests: Sender<(SocketAddr, Request)>, sampler: Arc<Sampler>, playback: Playback) -> Viewer {
         let server = Server::bind(addr).unwrap();
         server.set_nonblocking(true).unwrap();
 
@@ -61,16 +509,37 @@ impl Viewer {
             clients: HashMap::new(),
             agents: vec![],
             settings: None,
+            summaries: vec![],
+            playback,
+            requests,
+            sampler,
+            last_frame: Instant::now(),
+            last_report: Instant::now(),
         }
     }
 
-    fn send_message(&self, to: &mut ViewerClient, event: &Event) {
-        let encoded = serde_json::to_string(event).unwrap();
-        to.send_message(&websocket::Message::text(encoded)).ok();
+    fn world_radius(&self) -> f32 {
+        self.settings.as_ref().map_or(1.0, |settings| settings.world_radius)
+    }
+
+    fn queue(&self, to: &mut Connection, event: &Event) {
+        // Only frames differ between encodings, and none are sent directly.
+        to.queue(&encode(event, Encoding::Json, self.world_radius()), false);
     }
 
     fn accept_incoming(&mut self) {
         while let Ok(upgrade) = self.server.accept() {
+            let protocols = upgrade.protocols();
+            let (upgrade, encoding) = if protocols.iter().any(|protocol| protocol == BINARY_PROTOCOL) {
+                (upgrade.use_protocol(BINARY_PROTOCOL), Encoding::Binary)
+            }
+            else if protocols.iter().any(|protocol| protocol == JSON_PROTOCOL) {
+                (upgrade.use_protocol(JSON_PROTOCOL), Encoding::Json)
+            }
+            else {
+                (upgrade, Encoding::Json)
+            };
+
             match upgrade.accept() {
                 Err(e) => warn!("websocket accept error: {:?}", e),
                 Ok(mut client) => {
@@ -78,16 +547,21 @@ impl Viewer {
                         client.set_nodelay(true).unwrap();
                         client.set_nonblocking(true).unwrap();
 
+                        let mut connection = Connection::new(client, encoding);
                         if let Some(settings) = &self.settings {
-                            self.send_message(&mut client, &Event::Settings(settings.clone()));
+                            self.queue(&mut connection, &Event::Settings(settings.clone()));
+                        }
+                        self.queue(&mut connection, &Event::Spawn(self.agents.clone()));
+                        self.queue(&mut connection, &Event::Playback(self.playback));
+                        for summary in &self.summaries {
+                            self.queue(&mut connection, &Event::Summary(summary.clone()));
                         }
-                        self.send_message(&mut client, &Event::Spawn(self.agents.clone()));
 
-                        if let Some(old_client) = self.clients.insert(addr, client) {
-                            old_client.shutdown().unwrap();
+                        if let Some(old) = self.clients.insert(addr, connection) {
+                            old.client.shutdown().unwrap();
                             debug!("disconnected: {}", addr);
                         }
-                        debug!("connected: {}", addr);
+                        debug!("connected: {} ({:?})", addr, encoding);
                     }
                     else {
                         error!("couldn't get peer address");
@@ -97,11 +571,10 @@ impl Viewer {
         }
     }
 
-    fn publish_events(&mut self, receiver: &Receiver<Event>) {
-        use std::time::Duration;
+    fn publish_events(&mut self, receiver: &Receiver<Outgoing>) {
         const TIMEOUT: Duration = Duration::from_millis(1);
 
-        while let Ok(event) = receiver.recv_timeout(TIMEOUT) {
+        while let Ok(Outgoing { to, event }) = receiver.recv_timeout(TIMEOUT) {
             //debug!("Publishing to {} clients: {:?}", self.clients.len(), event);
 
             match &event {
@@ -111,68 +584,157 @@ impl Viewer {
                 },
                 Event::Clear => {
                     self.agents.clear();
+                    // A frame sampled before is of the agents just cleared.
+                    self.sampler.frame.lock().unwrap().take();
                 }
                 Event::Settings(settings) => {
                     self.settings = Some(settings.clone())
                 }
+                Event::Playback(playback) => {
+                    self.playback = *playback;
+                }
+                Event::Summary(summary) => {
+                    self.summaries.push(summary.clone());
+                }
                 _ => ()
             }
 
-            let encoded = serde_json::to_string(&event).unwrap();
-            let message = websocket::Message::text(encoded);
+            self.send(to, &event);
+        }
+    }
 
-            let mut disconnected_clients = vec![];
-            for (addr, client) in self.clients.iter_mut() {
-                match client.send_message(&message) {
-                    Err(_) => {
-                        // If sending fails, assume the connection is lost.
-                        // Shut it down for good measure.
-                        client.shutdown().ok();
-                        debug!("disconnected: {}", addr);
-                        disconnected_clients.push(addr.clone());
-                    }
-                    Ok(_) => {}
-                }
+    /// Send the latest sampled frame, and ask for the next one if anyone is
+    /// watching.
+    fn publish_frame(&mut self) {
+        let interval = self.settings.as_ref().map_or(0, |settings| settings.frame_interval);
+        if self.last_frame.elapsed() < Duration::from_millis(interval.into()) {
+            return;
+        }
+        self.last_frame = Instant::now();
+
+        let frame = self.sampler.frame.lock().unwrap().take();
+        if let Some(frame) = frame {
+            self.send(None, &frame);
+        }
+        if !self.clients.is_empty() {
+            self.sampler.wanted.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn send(&mut self, to: Option<SocketAddr>, event: &Event) {
+        // Encode once per encoding in use.
+        let world_radius = self.world_radius();
+        let mut messages: HashMap<Encoding, websocket::Message> = HashMap::new();
+        for (addr, connection) in self.clients.iter_mut() {
+            if to.is_some_and(|to| to != *addr) {
+                continue;
             }
+            let (encoding, frame) = match event {
+                Event::Frame { .. } => (connection.encoding, true),
+                _ => (Encoding::Json, false),
+            };
+            let message = messages.entry(encoding).or_insert_with(|| encode(event, encoding, world_radius));
+            connection.queue(message, frame);
+        }
+    }
 
-            // Clean up disconnected clients.
-            for addr in disconnected_clients {
-                self.clients.remove(&addr);
+    /// Write queued messages to every client, and report dropped frames now
+    /// and then.
+    fn flush_clients(&mut self) {
+        let report = self.last_report.elapsed() > Duration::from_secs(10);
+        if report {
+            self.last_report = Instant::now();
+        }
+
+        let mut disconnected_clients = vec![];
+        for (addr, connection) in self.clients.iter_mut() {
+            if !connection.flush() {
+                // If writing fails, assume the connection is lost. Shut it
+                // down for good measure.
+                connection.client.shutdown().ok();
+                debug!("disconnected: {} ({} frames dropped)", addr, connection.dropped);
+                disconnected_clients.push(*addr);
+            }
+            else if report && connection.dropped > connection.reported {
+                info!("dropped {} frames for slow client {}", connection.dropped - connection.reported, addr);
+                connection.reported = connection.dropped;
             }
         }
+
+        // Clean up disconnected clients.
+        for addr in disconnected_clients {
+            self.clients.remove(&addr);
+        }
     }
 
     fn receive_requests(&mut self) {
         //let mut disconnected_clients = vec![];
-        for (addr, client) in self.clients.iter_mut() {
+        for (addr, Connection { client, .. }) in self.clients.iter_mut() {
             if let Ok(message) = client.recv_message() {
                 debug!("message from {}: {:?}", addr, message);
                 match message {
                     OwnedMessage::Close(_) => { client.shutdown().ok(); }
+                    OwnedMessage::Text(text) => match serde_json::from_str(&text) {
+                        Ok(request) => self.requests.send((*addr, request)).unwrap(),
+                        Err(e) => warn!("invalid request from {}: {}", addr, e),
+                    },
                     _ => (),
                 };
             }
         }
     }
 
-    fn run(&mut self, receiver: Receiver<Event>) {
+    fn run(&mut self, receiver: Receiver<Outgoing>) {
         info!("viewer api started");
 
         loop {
             self.accept_incoming();
             self.receive_requests();
             self.publish_events(&receiver);
+            self.publish_frame();
+            self.flush_clients();
         }
     }
 }
 
-pub fn start_viewer() -> ViewerHandle {
-    let (sender, receiver) = channel();
+/// Start the viewer thread, playing back as `playback` until a client asks
+/// otherwise.
+pub fn start_viewer(playback: Playback) -> ViewerHandle {
+    let (sender, receiver) = sync_channel(EVENT_CAPACITY);
+    let (request_sender, requests) = channel();
+    let sampler = Arc::new(Sampler::default());
 
-    std::thread::spawn(|| {
-        let mut viewer = Viewer::new("0.0.0.0:29999");
+    let viewer_sampler = sampler.clone();
+    std::thread::spawn(move || {
+        let mut viewer = Viewer::new("0.0.0.0:29999", request_sender, viewer_sampler, playback);
         viewer.run(receiver);
     });
 
-    ViewerHandle::Mpsc(sender)
+    ViewerHandle::Mpsc {
+        events: sender,
+        lost: Cell::new(false),
+        requests,
+        playback: Cell::new(playback),
+        next_step: Cell::new(None),
+        sampler,
+        parents: RefCell::new(HashMap::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn speeds() {
+        let mut playback = Playback::default();
+        for speed in [f32::NAN, f32::INFINITY, -1.0, 0.0, 1e-30, 1e30] {
+            assert!(playback.set_speed(Some(speed)).is_err(), "accepted {}", speed);
+            assert_eq!(playback.speed, None);
+        }
+        for speed in [Some(0.01), Some(1.0), Some(1000.0), None] {
+            playback.set_speed(speed).unwrap();
+            assert_eq!(playback.speed, speed);
+        }
+    }
 }
@@ -0,0 +1,25 @@
+import { Playback, Request } from "./types";
+import "./Controls.css";
+
+interface ControlsProps {
+    playback: Playback;
+    onRequest: (request: Request) => void;
+}
+
+// Multiples of real time, and `null` for as fast as possible.
+const SPEEDS: (number | null)[] = [0.25, 1, 4, 16, null];
+
+export const Controls = (props: ControlsProps) => {
+    const { paused, speed } = props.playback;
+    return (
+        <div className="Controls">
+            <div onClick={() => props.onRequest(paused ? "Resume" : "Pause")}>{paused ? "resume" : "pause"}</div>
+            <div onClick={() => props.onRequest("Step")}>step</div>
+            {SPEEDS.map(option =>
+                <div key={String(option)} className={option === speed ? "selected" : ""} onClick={() => props.onRequest({ Speed: option })}>
+                    {option === null ? "max" : `${option}×`}
+                </div>
+            )}
+        </div>
+    );
+}
@@ -0,0 +1,30 @@
+import { Summary } from "./types";
+import "./Summaries.css";
+
+interface SummariesProps {
+    summaries: Summary[];
+}
+
+// Bars for the most recent generations.
+const MAX_BARS = 100;
+
+export const Summaries = (props: SummariesProps) => {
+    const last = props.summaries[props.summaries.length - 1];
+    if (!last) {
+        return null;
+    }
+
+    return (
+        <div className="Summaries">
+            <div className="Last">
+                generation {last.generation}: {last.survivors} of {last.agents} survived,
+                mean fitness {last.fitness_mean.toFixed(2)}
+            </div>
+            <div className="SurvivalRate">
+                {props.summaries.slice(-MAX_BARS).map(summary =>
+                    <div key={summary.generation} title={`generation ${summary.generation}`} style={{height: `${summary.survival_rate * 100}%`}}/>
+                )}
+            </div>
+        </div>
+    );
+}
@@ -1,8 +1,11 @@
-import { useEffect, useState } from 'react';
-import { AgentInfo, Frame, Settings } from './types';
+import { useEffect, useRef, useState } from 'react';
+import { AgentInfo, AgentState, Frame, Kill, Playback, Request, Settings, Summary } from './types';
 import './Viewer.css';
 import { World } from "./World";
 import { UI } from "./UI";
+import { Controls } from "./Controls";
+import { Summaries } from "./Summaries";
+import { decodeFrame } from "./util";
 
 interface ViewerProps {
     url: string,
@@ -21,7 +24,7 @@ export const Viewer = (props: ViewerProps) => {
     const [disconnected, setDisconnected] = useState(false);
 
     const [agents, setAgents] = useState<AgentInfo[]>([]);
-    const [[frameNumber, frame], setFrame] = useState<[number, Frame]>([0, []]);
+    const [[simulationTime, frame], setFrame] = useState<[number, Frame]>([0, []]);
     const [settings, setSettings] = useState<Settings>({
         title: "",
         world_radius: 0,
@@ -34,6 +37,13 @@ export const Viewer = (props: ViewerProps) => {
     const [highlight, setHighlight] = useState<string>("");
     const [showUi, setShowUi] = useState(false);
     const [startTime, setStartTime] = useState(new Date());
+    const [playback, setPlayback] = useState<Playback>({ paused: false, speed: null });
+    const [summaries, setSummaries] = useState<Summary[]>([]);
+    const socketRef = useRef<WebSocket | null>(null);
+
+    const onRequest = (request: Request) => {
+        socketRef.current?.send(JSON.stringify(request));
+    };
 
     const onClear = () => {
         setAgents([]);
@@ -43,19 +53,19 @@ export const Viewer = (props: ViewerProps) => {
 
     const onSpawn = (newAgents: AgentInfo[]) => {
         // Oh no, this is broken, I believe!
-        setFrame([frameNumber, [...frame, ...newAgents.map(agent => [0, 0] as [number, number])]]);
+        setFrame([simulationTime, [...frame, ...newAgents.map(agent => [0, 0, 0, 0, 0] as AgentState)]]);
         setAgents([...agents, ...newAgents]);
     }
 
-    const onKill = (indices: number[]) => {
+    const onKill = (kill: Kill) => {
         // Oh no, this is broken, I believe!
-        setAgents(agents.filter((_, i) => i in indices));
-        setFrame([frameNumber, frame.filter((_, i) => i in indices)]);
+        setAgents(agents.filter((_, i) => !kill.died.includes(i)));
+        setFrame([simulationTime, frame.filter((_, i) => !kill.died.includes(i))]);
     }
 
-    const onFrame = (num: number, frame: Frame) => {
+    const onFrame = (time: number, frame: Frame) => {
         // Oh no, this is broken!
-        setFrame([num, frame]);
+        setFrame([time, frame]);
     }
 
     const onSettings = (settings: Settings) => {
@@ -78,8 +88,9 @@ export const Viewer = (props: ViewerProps) => {
         }
         else {
             console.log("connecting to publisher");
-            let socket = new WebSocket(props.url);
-            let frameNumber = 0;
+            let socket = new WebSocket(props.url, ["evolution.binary", "evolution.json"]);
+            socket.binaryType = "arraybuffer";
+            socketRef.current = socket;
 
             socket.onopen = () => {
                 console.log("connected");
@@ -91,20 +102,27 @@ export const Viewer = (props: ViewerProps) => {
                 setStatus(Status.DISCONNECTED);
                 setDisconnected(true);
                 onClear();
+                // New connections are sent every summary again.
+                setSummaries([]);
             }
 
             socket.onmessage = message => {
+                if (message.data instanceof ArrayBuffer) {
+                    onFrame(...decodeFrame(message.data));
+                    return;
+                }
                 const event = JSON.parse(message.data);
                 if (event === "Clear") {
                     onClear();
-                    frameNumber = 0;
                 }
                 else if ("Spawn" in event) onSpawn(event["Spawn"]);
                 else if ("Kill" in event) onKill(event["Kill"]);
                 else if ("Frame" in event) {
-                    onFrame(++frameNumber, event["Frame"]);
+                    onFrame(event["Frame"].time, event["Frame"].agents);
                 }
                 else if ("Settings" in event) onSettings(event["Settings"]);
+                else if ("Playback" in event) setPlayback(event["Playback"]);
+                else if ("Summary" in event) setSummaries(summaries => [...summaries, event["Summary"]]);
                 else console.log("unknown message: ", event);
             };
 
@@ -131,14 +149,14 @@ export const Viewer = (props: ViewerProps) => {
         default:
     }
 
-    console.log(frameNumber)
-
     return (
         <div className="Viewer">
             <div className="Panes">
                 {props.ui ? <UI highlight={showUi ? highlight : ""} onHighlight={id => setHighlight(id)} agents={agents} show={showUi} onToggle={show => setShowUi(show)}/> : null}
-                <World simulationTime={frameNumber * settings.time_step} startTime={startTime} highlight={showUi ? highlight : ""} settings={settings} agents={agents} frame={frame} onHighlight={id => setHighlight(id)}/>
+                <World simulationTime={simulationTime} startTime={startTime} highlight={showUi ? highlight : ""} settings={settings} agents={agents} frame={frame} onHighlight={id => setHighlight(id)}/>
             </div>
+            {status === Status.CONNECTED ? <Summaries summaries={summaries}/> : null}
+            {status === Status.CONNECTED ? <Controls playback={playback} onRequest={onRequest}/> : null}
             {issue}
         </div>
     );
@@ -1,5 +1,5 @@
 import { useEffect, useMemo, useRef } from "react";
-import { AgentInfo, Frame, Settings } from "./types";
+import { AgentInfo, AgentState, Frame, Settings } from "./types";
 import { agentColor } from "./util";
 import "./World.css";
 
@@ -16,7 +16,7 @@ interface WorldProps {
 interface AgentProps {
     info: AgentInfo;
     highlight: boolean;
-    position: [number, number];
+    position: AgentState;
     onHighlight: (id: string) => void;
 }
 
@@ -27,10 +27,13 @@ const Agent = (props: AgentProps) => {
         marginTop: props.position[1] - 4,
         backgroundColor: color,
         boxShadow: props.highlight ? `0px 0px 20px 20px #bbc` : "none", //`0px 0px 20px 5px ${color}`,
+        transform: `rotate(${props.position[2]}rad)`,
     };
 
     return (
-        <div className="Agent" style={style} onClick={() => props.onHighlight(props.info[0])}></div>
+        <div className="Agent" style={style} onClick={() => props.onHighlight(props.info[0])}>
+            <div className="Heading" style={{backgroundColor: color}}/>
+        </div>
     );
 }
 
@@ -1,6 +1,8 @@
 
 export type AgentInfo = [string, number[]];
-export type Frame = [number, number][];
+// Per agent: [x, y, heading, vx, vy].
+export type AgentState = [number, number, number, number, number];
+export type Frame = AgentState[];
 export interface Settings {
     title: string;
     world_radius: number;
@@ -13,3 +15,43 @@ export interface Settings {
         radius: number,
     };
 }
+export interface Kill {
+    died: number[];
+    survivors: number;
+    zone: { x: number, y: number, radius: number };
+}
+// Per-generation statistics; see `GenerationStats`.
+export interface Summary {
+    generation: number;
+    agents: number;
+    survivors: number;
+    survival_rate: number;
+    fitness_mean: number;
+    [field: string]: unknown;
+}
+export interface Playback {
+    paused: boolean;
+    // Multiple of real time, or null for as fast as possible.
+    speed: number | null;
+}
+export interface Snapshot {
+    time: number;
+    zone: { x: number, y: number, radius: number };
+    agents: [string, AgentState][];
+}
+// Requests to the simulation, sent as JSON.
+export type Request =
+    "Pause" | "Resume" | "Step" | "SkipGeneration" | "Snapshot" |
+    { Speed: number | null } |
+    { Describe: { agent: string, dot?: boolean } };
+export interface AgentDescription {
+    id: string;
+    ancestry: string[];
+    genome: number[];
+    activations: number[];
+    graph: {
+        neurons: { index: number, label: string, bias: number }[];
+        connections: { from: number, to: number, weight: number }[];
+    };
+    dot?: string;
+}
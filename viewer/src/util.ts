@@ -1,3 +1,5 @@
+import { Frame } from "./types";
+
 
 export function agentColor(genome: number[]): string {
     let hue = genome
@@ -5,3 +7,23 @@ export function agentColor(genome: number[]): string {
         .reduce((a,b) => a+b, 0);
     return `hsl(${hue * 20 % 360}deg 100% 50%)`;
 }
+
+// Decode a binary frame; see `encode_frame` in src/viewer.rs.
+export function decodeFrame(data: ArrayBuffer): [number, Frame] {
+    const view = new DataView(data);
+    const count = view.getUint32(1, true);
+    const time = view.getFloat32(5, true);
+    const positionScale = view.getFloat32(9, true) / 32767;
+    const velocityScale = view.getFloat32(13, true) / 32767;
+    const frame: Frame = [];
+    for (let i = 0, offset = 17; i < count; i++, offset += 10) {
+        frame.push([
+            view.getInt16(offset, true) * positionScale,
+            view.getInt16(offset + 2, true) * positionScale,
+            view.getUint16(offset + 8, true) / 65536 * 2 * Math.PI,
+            view.getInt16(offset + 4, true) * velocityScale,
+            view.getInt16(offset + 6, true) * velocityScale,
+        ]);
+    }
+    return [time, frame];
+}